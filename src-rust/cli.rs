@@ -0,0 +1,59 @@
+use std::io::Read;
+
+use crate::services::CertificateService;
+
+const USAGE: &str = "usage: verichain verify-cert <certificate-file | -> [--public-key <base64url>]";
+
+/// `verify-cert`: checks a shipment certificate offline against a trusted
+/// public key, given with `--public-key` or `VERICHAIN_PUBLIC_KEY`. Prints the
+/// claims and exits 0 when valid, 1 when not and 2 on usage errors.
+pub fn verify_cert(args: &[String]) -> i32 {
+    let mut path = None;
+    let mut public_key = std::env::var("VERICHAIN_PUBLIC_KEY").ok();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--public-key" => public_key = args.next().cloned(),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        }
+    }
+    
+    let (Some(path), Some(public_key)) = (path, public_key) else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    let Some(public_key) = CertificateService::parse_public_key(&public_key) else {
+        eprintln!("Public key must be a base64url-encoded 32-byte Ed25519 key");
+        return 2;
+    };
+    
+    let certificate = if path == "-" {
+        let mut certificate = String::new();
+        std::io::stdin().read_to_string(&mut certificate).map(|_| certificate)
+    } else {
+        std::fs::read_to_string(&path)
+    };
+    let certificate = match certificate {
+        Ok(certificate) => certificate,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return 2;
+        }
+    };
+    
+    match CertificateService::verify(&certificate, &public_key) {
+        Ok(claims) => {
+            println!("✅ Certificate is valid");
+            println!("{}", serde_json::to_string_pretty(&claims).expect("claims serialize"));
+            0
+        }
+        Err(e) => {
+            eprintln!("❌ Certificate is invalid: {}", e);
+            1
+        }
+    }
+}
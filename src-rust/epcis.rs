@@ -0,0 +1,442 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::errors::EpcisError;
+use crate::gs1;
+use crate::models::{Coordinates, EnvironmentalMetric, EventType, Shipment, ShipmentEvent, TelemetryReading};
+use crate::services::TelemetryService;
+
+/// EPCIS documents are JSON-LD; serving them under their own media type also
+/// keeps `middleware::wire_format` from camel-casing what is already camelCase.
+pub const MEDIA_TYPE: &str = "application/ld+json";
+
+const CONTEXT: &str = "https://ref.gs1.org/standards/epcis/epcis-context.jsonld";
+/// Namespace of the `verichain:` extension fields carrying what EPCIS has no slot for.
+const NAMESPACE: &str = "https://verichain.app/epcis/";
+const EVENT_ID_PREFIX: &str = "urn:verichain:event:";
+const STANDARD_GRAVITY: f64 = 9.80665;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpcisDocument {
+    #[serde(rename = "@context")]
+    pub context: Value,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub schema_version: String,
+    pub creation_date: DateTime<Utc>,
+    pub epcis_body: EpcisBody,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpcisBody {
+    pub event_list: Vec<EpcisEvent>,
+}
+
+/// An EPCIS event. Only `ObjectEvent`s are produced or imported; the optional
+/// fields let documents mixing in other event types still parse.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpcisEvent {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(rename = "eventID", default, skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
+    pub event_time: DateTime<Utc>,
+    #[serde(default)]
+    pub event_time_zone_offset: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub epc_list: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quantity_list: Vec<QuantityElement>,
+    #[serde(default)]
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub biz_step: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disposition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_point: Option<ReadPoint>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensor_element_list: Vec<SensorElement>,
+    #[serde(rename = "verichain:location", default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(rename = "verichain:reporter", default, skip_serializing_if = "Option::is_none")]
+    pub reporter: Option<String>,
+    #[serde(rename = "verichain:verifiedTemperature", default, skip_serializing_if = "Option::is_none")]
+    pub verified_temperature: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuantityElement {
+    pub epc_class: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uom: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadPoint {
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SensorElement {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensor_metadata: Option<Value>,
+    #[serde(default)]
+    pub sensor_report: Vec<SensorReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SensorReport {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uom: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exception: Option<String>,
+}
+
+/// CBV business step and disposition for each custody event type.
+fn biz_step(event_type: &EventType) -> (&'static str, &'static str) {
+    match event_type {
+        EventType::Pickup => ("shipping", "in_transit"),
+        EventType::Transit => ("transporting", "in_transit"),
+        EventType::Delivery => ("receiving", "in_progress"),
+    }
+}
+
+/// Reads a CBV business step given bare (`shipping`), as a URN or as a GS1 web URI.
+fn event_type(biz_step: &str) -> Option<EventType> {
+    let step = biz_step.rsplit([':', '-', '/']).next().unwrap_or(biz_step);
+    match step {
+        "shipping" | "departing" | "loading" => Some(EventType::Pickup),
+        "transporting" | "arriving" | "holding" | "storing" => Some(EventType::Transit),
+        "receiving" | "accepting" | "unloading" => Some(EventType::Delivery),
+        _ => None,
+    }
+}
+
+/// Identifies the shipment's goods: SGTIN Digital Links for serialized units,
+/// and a quantity of each lot (or, without a GTIN, of each product) otherwise.
+fn what(shipment: &Shipment) -> (Vec<String>, Vec<QuantityElement>) {
+    let mut epc_list = Vec::new();
+    let mut quantity_list = Vec::new();
+    for item in &shipment.line_items {
+        let labels = gs1::line_item_labels(gs1::DEFAULT_RESOLVER, item);
+        match labels {
+            Some(labels) if !item.serials.is_empty() => epc_list.extend(labels.into_iter().map(|label| label.digital_link)),
+            _ => quantity_list.push(QuantityElement {
+                epc_class: match labels.and_then(|labels| labels.into_iter().next()) {
+                    Some(label) => label.digital_link,
+                    None => format!("urn:verichain:product:{}", item.product_id),
+                },
+                quantity: Some(item.quantity),
+                uom: item.unit.un_code().map(str::to_string),
+            }),
+        }
+    }
+    (epc_list, quantity_list)
+}
+
+fn sensor_report(kind: &str, value: Option<f64>, uom: &str, alarm: bool) -> Option<SensorReport> {
+    Some(SensorReport {
+        kind: kind.to_string(),
+        value: Some(value?),
+        uom: Some(uom.to_string()),
+        exception: alarm.then(|| "ALARM_CONDITION".to_string()),
+    })
+}
+
+/// Maps a shipment's creation and custody events onto EPCIS `ObjectEvent`s.
+pub fn export(shipment: &Shipment) -> EpcisDocument {
+    let (epc_list, quantity_list) = what(shipment);
+    
+    // Funding the escrow is when the goods enter the record
+    let mut events = vec![EpcisEvent {
+        kind: "ObjectEvent".to_string(),
+        event_id: Some(format!("{}{}:created", EVENT_ID_PREFIX, shipment.id)),
+        event_time: shipment.created_at,
+        event_time_zone_offset: "+00:00".to_string(),
+        epc_list: epc_list.clone(),
+        quantity_list: quantity_list.clone(),
+        action: "ADD".to_string(),
+        biz_step: Some("commissioning".to_string()),
+        disposition: Some("active".to_string()),
+        read_point: None,
+        sensor_element_list: vec![],
+        location: None,
+        reporter: Some(shipment.manufacturer.clone()),
+        verified_temperature: None,
+    }];
+    
+    for event in &shipment.events {
+        let (step, disposition) = biz_step(&event.event_type);
+        let alarm = |metric| event.violations.iter().any(|violation| violation.metric == metric);
+        let reports = [
+            sensor_report("gs1:Temperature", Some(event.temperature), "CEL", alarm(EnvironmentalMetric::Temperature)),
+            sensor_report("gs1:RelativeHumidity", event.humidity, "P1", alarm(EnvironmentalMetric::Humidity)),
+            sensor_report("gs1:Illuminance", event.light, "LUX", alarm(EnvironmentalMetric::Light)),
+            sensor_report("gs1:Acceleration", event.shock.map(|g| g * STANDARD_GRAVITY), "MSK", alarm(EnvironmentalMetric::Shock)),
+        ];
+        
+        events.push(EpcisEvent {
+            kind: "ObjectEvent".to_string(),
+            event_id: Some(format!("{}{}", EVENT_ID_PREFIX, event.id)),
+            event_time: event.timestamp,
+            event_time_zone_offset: "+00:00".to_string(),
+            epc_list: epc_list.clone(),
+            quantity_list: quantity_list.clone(),
+            action: "OBSERVE".to_string(),
+            biz_step: Some(step.to_string()),
+            disposition: Some(disposition.to_string()),
+            read_point: event.coordinates.map(|c| ReadPoint { id: format!("geo:{},{}", c.latitude, c.longitude) }),
+            sensor_element_list: vec![SensorElement {
+                sensor_metadata: Some(json!({ "time": event.timestamp })),
+                sensor_report: reports.into_iter().flatten().collect(),
+            }],
+            location: Some(event.location.clone()),
+            reporter: Some(event.reporter.clone()),
+            verified_temperature: Some(event.verified_temperature),
+        });
+    }
+    
+    EpcisDocument {
+        context: json!([CONTEXT, { "verichain": NAMESPACE }]),
+        kind: "EPCISDocument".to_string(),
+        schema_version: "2.0".to_string(),
+        creation_date: Utc::now(),
+        epcis_body: EpcisBody { event_list: events },
+    }
+}
+
+/// Converts the custody `ObjectEvent`s of a document into shipment events,
+/// checked against the limits of the shipment's products. Events whose id the
+/// shipment already has are skipped, so re-importing a document adds nothing.
+/// New events can't predate the shipment's last one, since they're applied in
+/// order after it. Creation and other non-custody events are ignored.
+pub fn import(shipment: &Shipment, document: EpcisDocument) -> Result<Vec<ShipmentEvent>, EpcisError> {
+    if document.kind != "EPCISDocument" {
+        return Err(EpcisError::UnsupportedDocument(document.kind));
+    }
+    
+    let mut events = Vec::new();
+    for (i, epcis_event) in document.epcis_body.event_list.into_iter().enumerate() {
+        let event_type = match epcis_event.biz_step.as_deref().and_then(event_type) {
+            Some(event_type) if epcis_event.kind == "ObjectEvent" && epcis_event.action == "OBSERVE" => event_type,
+            _ => continue,
+        };
+        
+        let id = match &epcis_event.event_id {
+            Some(event_id) => event_id.strip_prefix(EVENT_ID_PREFIX).unwrap_or(event_id).to_string(),
+            None => format!("event-{}", Uuid::new_v4()),
+        };
+        if shipment.events.iter().chain(events.iter()).any(|event: &ShipmentEvent| event.id == id) {
+            continue;
+        }
+        if shipment.events.last().is_some_and(|last| epcis_event.event_time < last.timestamp) {
+            return Err(EpcisError::PredatesRecordedEvents(i));
+        }
+        
+        let mut reading = TelemetryReading {
+            timestamp: epcis_event.event_time,
+            temperature: None,
+            humidity: None,
+            shock: None,
+            light: None,
+        };
+        for report in epcis_event.sensor_element_list.iter().flat_map(|element| &element.sensor_report) {
+            let Some(value) = report.value else { continue };
+            match report.kind.rsplit([':', '/']).next().unwrap_or(&report.kind) {
+                "Temperature" => reading.temperature = Some(celsius(value, report.uom.as_deref())),
+                "RelativeHumidity" => reading.humidity = Some(value),
+                "Illuminance" => reading.light = Some(value),
+                "Acceleration" => reading.shock = Some(value / STANDARD_GRAVITY),
+                _ => {}
+            }
+        }
+        let temperature = reading.temperature.ok_or(EpcisError::MissingTemperature(i))?;
+        
+        let coordinates = epcis_event.read_point.as_ref().and_then(|read_point| geo_coordinates(&read_point.id));
+        let location = epcis_event
+            .location
+            .or_else(|| epcis_event.read_point.map(|read_point| read_point.id))
+            .ok_or(EpcisError::MissingLocation(i))?;
+        
+        // There is no weather cross-check for past events, so the sensor reading decides
+        // compliance. A partner's own "verified" value can add a violation, never clear one.
+        let limits = shipment.limits();
+        let mut violations = TelemetryService::check_reading(&limits, &reading);
+        let verified_temperature = epcis_event.verified_temperature.unwrap_or(temperature);
+        if !violations.iter().any(|violation| violation.metric == EnvironmentalMetric::Temperature) {
+            let verified = TelemetryReading {
+                temperature: Some(verified_temperature),
+                ..reading.clone()
+            };
+            violations.extend(
+                TelemetryService::check_reading(&limits, &verified)
+                    .into_iter()
+                    .filter(|violation| violation.metric == EnvironmentalMetric::Temperature),
+            );
+        }
+        
+        events.push(ShipmentEvent {
+            id,
+            timestamp: epcis_event.event_time,
+            location,
+            temperature,
+            verified_temperature,
+            reporter: epcis_event.reporter.unwrap_or_else(|| "epcis-import".to_string()),
+            event_type,
+            is_temperature_valid: !violations.iter().any(|violation| violation.metric == EnvironmentalMetric::Temperature),
+            humidity: reading.humidity,
+            shock: reading.shock,
+            light: reading.light,
+            violations,
+            coordinates,
+            hash: String::new(),
+        });
+    }
+    
+    events.sort_by_key(|event| event.timestamp);
+    Ok(events)
+}
+
+fn celsius(value: f64, uom: Option<&str>) -> f64 {
+    match uom {
+        Some("FAH") => (value - 32.0) * 5.0 / 9.0,
+        Some("KEL") => value - 273.15,
+        _ => value,
+    }
+}
+
+fn geo_coordinates(uri: &str) -> Option<Coordinates> {
+    let (latitude, longitude) = uri.strip_prefix("geo:")?.split(';').next()?.split_once(',')?;
+    let coordinates = Coordinates {
+        latitude: latitude.parse().ok()?,
+        longitude: longitude.split(',').next()?.parse().ok()?,
+    };
+    coordinates.validate().is_ok().then_some(coordinates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+
+    #[actix_web::test]
+    async fn export_round_trips_through_import() {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        let mut shipment = storage.get_shipment("ship-001").expect("mock shipment");
+        shipment.events.push(ShipmentEvent {
+            id: "event-1".to_string(),
+            timestamp: Utc::now(),
+            location: "Mumbai".to_string(),
+            temperature: 46.4,
+            verified_temperature: 9.5,
+            reporter: "logi-001".to_string(),
+            event_type: EventType::Pickup,
+            is_temperature_valid: false,
+            humidity: Some(40.0),
+            shock: Some(2.0),
+            light: None,
+            violations: vec![],
+            coordinates: Some(Coordinates { latitude: 19.07, longitude: 72.87 }),
+            hash: String::new(),
+        });
+        
+        let document: EpcisDocument = serde_json::from_value(serde_json::to_value(export(&shipment)).unwrap()).unwrap();
+        assert_eq!(document.epcis_body.event_list.len(), 2);
+        
+        let exported = shipment.events.pop().unwrap();
+        let imported = import(&shipment, document).expect("document imports");
+        assert_eq!(imported.len(), 1);
+        let event = &imported[0];
+        assert_eq!(event.id, exported.id);
+        assert_eq!(event.location, exported.location);
+        assert_eq!(event.verified_temperature, exported.verified_temperature);
+        assert!((event.shock.unwrap() - 2.0).abs() < 1e-9);
+        assert_eq!(event.coordinates.map(|c| c.latitude), Some(19.07));
+        assert!(!event.is_temperature_valid, "9.5 °C is outside the mock product's 2-8 °C range");
+        
+        // Re-importing what the shipment already has adds nothing
+        shipment.events.push(exported);
+        let document: EpcisDocument = serde_json::from_value(serde_json::to_value(export(&shipment)).unwrap()).unwrap();
+        assert!(import(&shipment, document).unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn import_refuses_events_older_than_the_recorded_ones() {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        let mut shipment = storage.get_shipment("ship-001").expect("mock shipment");
+        let event = |id: &str, timestamp| ShipmentEvent {
+            id: id.to_string(),
+            timestamp,
+            location: "Mumbai".to_string(),
+            temperature: 5.0,
+            verified_temperature: 5.0,
+            reporter: "logi-001".to_string(),
+            event_type: EventType::Transit,
+            is_temperature_valid: true,
+            humidity: None,
+            shock: None,
+            light: None,
+            violations: vec![],
+            coordinates: None,
+            hash: String::new(),
+        };
+        shipment.events.push(event("event-late", Utc::now() - chrono::Duration::hours(1)));
+        let document = serde_json::to_value(export(&shipment)).unwrap();
+        
+        // Already recorded, so skipped rather than refused
+        shipment.events.push(event("event-now", Utc::now()));
+        assert!(import(&shipment, serde_json::from_value(document.clone()).unwrap()).unwrap().is_empty());
+        
+        shipment.events.remove(0);
+        let result = import(&shipment, serde_json::from_value(document).unwrap());
+        assert!(matches!(result, Err(EpcisError::PredatesRecordedEvents(1))));
+    }
+
+    #[actix_web::test]
+    async fn import_checks_the_sensor_reading_not_the_claimed_one() {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        let mut shipment = storage.get_shipment("ship-001").expect("mock shipment");
+        shipment.events.push(ShipmentEvent {
+            id: "event-1".to_string(),
+            timestamp: Utc::now(),
+            location: "Mumbai".to_string(),
+            temperature: 30.0,
+            verified_temperature: 5.0,
+            reporter: "logi-001".to_string(),
+            event_type: EventType::Transit,
+            is_temperature_valid: true,
+            humidity: None,
+            shock: None,
+            light: None,
+            violations: vec![],
+            coordinates: None,
+            hash: String::new(),
+        });
+        
+        let document: EpcisDocument = serde_json::from_value(serde_json::to_value(export(&shipment)).unwrap()).unwrap();
+        shipment.events.clear();
+        let imported = import(&shipment, document).expect("document imports");
+        
+        let event = &imported[0];
+        assert!(!event.is_temperature_valid, "30 °C from the sensor is outside 2-8 °C whatever the claimed value");
+        assert!(event.violations.iter().any(|violation| violation.metric == EnvironmentalMetric::Temperature));
+    }
+}
@@ -0,0 +1,162 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use thiserror::Error;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::models::{ApiResponse, FieldError};
+
+/// Errors surfaced by the API. Each variant maps to an HTTP status and a
+/// stable `code` in the response body that clients can branch on.
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Validation(String),
+    #[error("Request validation failed")]
+    InvalidRequest(Vec<FieldError>),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    PreconditionFailed(String),
+    #[error("{0}")]
+    IdempotencyKeyReused(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    Upstream(String),
+}
+
+/// Why a shipment certificate failed offline verification.
+#[derive(Debug, Error)]
+pub enum CertificateError {
+    #[error("Certificate is not a well-formed JWS")]
+    Malformed,
+    #[error("Unsupported signature algorithm {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("Certificate was signed by key {0}, which is not the key provided")]
+    UnknownKey(String),
+    #[error("Signature does not match the certificate contents")]
+    BadSignature,
+}
+
+/// Why a GS1 identifier, Digital Link URI or element string was rejected.
+#[derive(Debug, Error)]
+pub enum Gs1Error {
+    #[error("{0} is not an 8, 12, 13 or 14 digit GTIN")]
+    InvalidGtin(String),
+    #[error("GTIN {0} has an invalid check digit")]
+    InvalidCheckDigit(String),
+    #[error("({ai}) {value} must be 1-20 characters from the GS1 character set")]
+    InvalidAttribute { ai: &'static str, value: String },
+    #[error("{0} is not a YYMMDD date")]
+    InvalidDate(String),
+    #[error("Application identifier ({0}) is not supported")]
+    UnsupportedAi(String),
+    #[error("Malformed GS1 data: {0}")]
+    Malformed(String),
+}
+
+/// Why an EPCIS document couldn't be imported. Event positions index `epcisBody.eventList`.
+#[derive(Debug, Error)]
+pub enum EpcisError {
+    #[error("Expected an EPCISDocument, got {0}")]
+    UnsupportedDocument(String),
+    #[error("Event {0} has no temperature sensor report")]
+    MissingTemperature(usize),
+    #[error("Event {0} has neither a verichain:location nor a readPoint")]
+    MissingLocation(usize),
+    #[error("Event {0} predates the shipment's last recorded event")]
+    PredatesRecordedEvents(usize),
+}
+
+impl From<EpcisError> for ApiError {
+    fn from(err: EpcisError) -> Self {
+        ApiError::Validation(err.to_string())
+    }
+}
+
+impl From<Gs1Error> for ApiError {
+    fn from(err: Gs1Error) -> Self {
+        ApiError::Validation(err.to_string())
+    }
+}
+
+impl ApiError {
+    pub fn not_found(resource: &str) -> Self {
+        ApiError::NotFound(format!("{} not found", resource))
+    }
+    
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Validation(_) | ApiError::InvalidRequest(_) => "validation_failed",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::IdempotencyKeyReused(_) => "idempotency_key_reused",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Upstream(_) => "upstream_unavailable",
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Validation(_) | ApiError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::IdempotencyKeyReused(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+    
+    fn error_response(&self) -> HttpResponse {
+        let mut body = ApiResponse::<()>::error(self.code(), self.to_string());
+        if let ApiError::InvalidRequest(errors) = self {
+            body = body.with_errors(errors.clone());
+        }
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut fields = Vec::new();
+        collect_field_errors(None, errors, &mut fields);
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+        ApiError::InvalidRequest(fields)
+    }
+}
+
+/// Flattens nested validation errors into dotted field paths, e.g. `escrow.currency`.
+fn collect_field_errors(prefix: Option<&str>, errors: ValidationErrors, fields: &mut Vec<FieldError>) {
+    for (field, kind) in errors.into_errors() {
+        let path = match (prefix, field) {
+            (prefix, "__all__") => prefix.map(str::to_string),
+            (Some(prefix), field) => Some(format!("{}.{}", prefix, field)),
+            (None, field) => Some(field.to_string()),
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                fields.extend(errors.into_iter().map(|error| FieldError {
+                    field: path.clone(),
+                    message: error.message.map(|m| m.into_owned()).unwrap_or_else(|| error.code.to_string()),
+                    code: error.code.into_owned(),
+                }));
+            }
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(path.as_deref(), *errors, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    let path = format!("{}[{}]", path.as_deref().unwrap_or_default(), index);
+                    collect_field_errors(Some(&path), *errors, fields);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use chrono::{Datelike, NaiveDate};
+
+use crate::errors::Gs1Error;
+use crate::models::{Gs1Identifiers, Gs1Label, LineItem, Shipment};
+
+/// Resolver used for GS1 Digital Link URIs unless a brand runs its own.
+pub const DEFAULT_RESOLVER: &str = "https://id.gs1.org";
+
+/// The FNC1 separator that ends a variable-length element in GS1-128 barcode data.
+pub const GROUP_SEPARATOR: char = '\u{1d}';
+
+/// Application identifiers we read and write, with their fixed length or `None`
+/// for variable-length elements of up to 20 characters.
+const AIS: [(&str, Option<usize>); 4] = [("01", Some(14)), ("17", Some(6)), ("10", None), ("21", None)];
+
+const MAX_ATTRIBUTE_LEN: usize = 20;
+
+/// GS1 mod-10 check digit for `body`, the identifier without its last digit.
+pub fn check_digit(body: &str) -> u32 {
+    let sum: u32 = body
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit * 3 } else { digit })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+/// Checks a GTIN-8, -12, -13 or -14 and returns it zero-padded to 14 digits.
+pub fn normalize_gtin(gtin: &str) -> Result<String, Gs1Error> {
+    if ![8, 12, 13, 14].contains(&gtin.len()) || !gtin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(Gs1Error::InvalidGtin(gtin.to_string()));
+    }
+    let (body, check) = gtin.split_at(gtin.len() - 1);
+    if check.parse::<u32>().ok() != Some(check_digit(body)) {
+        return Err(Gs1Error::InvalidCheckDigit(gtin.to_string()));
+    }
+    Ok(format!("{:0>14}", gtin))
+}
+
+/// Lots and serials are 1-20 characters from the GS1 AI encodable character set 82.
+pub fn is_valid_attribute(value: &str) -> bool {
+    (1..=MAX_ATTRIBUTE_LEN).contains(&value.len())
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || "!\"%&'()*+,-./:;<=>?_".contains(c))
+}
+
+fn check_attribute(ai: &'static str, value: &str) -> Result<(), Gs1Error> {
+    if is_valid_attribute(value) {
+        Ok(())
+    } else {
+        Err(Gs1Error::InvalidAttribute { ai, value: value.to_string() })
+    }
+}
+
+/// Encodes a date as `YYMMDD`.
+pub fn encode_date(date: NaiveDate) -> String {
+    format!("{:02}{:02}{:02}", date.year() % 100, date.month(), date.day())
+}
+
+/// Decodes `YYMMDD`, reading years as 20YY. A day of `00` means the last day of the month.
+pub fn decode_date(value: &str) -> Result<NaiveDate, Gs1Error> {
+    let invalid = || Gs1Error::InvalidDate(value.to_string());
+    if value.len() != 6 || !value.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let year = 2000 + value[0..2].parse::<i32>().map_err(|_| invalid())?;
+    let month = value[2..4].parse::<u32>().map_err(|_| invalid())?;
+    match value[4..6].parse::<u32>().map_err(|_| invalid())? {
+        0 => NaiveDate::from_ymd_opt(year, month, 1)
+            .and_then(|first| first.checked_add_months(chrono::Months::new(1)))
+            .and_then(|next| next.pred_opt())
+            .ok_or_else(invalid),
+        day => NaiveDate::from_ymd_opt(year, month, day).ok_or_else(invalid),
+    }
+}
+
+/// GS1 Digital Link URI, e.g. `https://id.gs1.org/01/09506000134352/10/LOT1/21/SN1?17=270131`.
+pub fn digital_link(resolver: &str, ids: &Gs1Identifiers) -> String {
+    let mut uri = format!("{}/01/{}", resolver.trim_end_matches('/'), ids.gtin);
+    if let Some(lot) = &ids.lot {
+        uri.push_str(&format!("/10/{}", percent_encode(lot)));
+    }
+    if let Some(serial) = &ids.serial {
+        uri.push_str(&format!("/21/{}", percent_encode(serial)));
+    }
+    if let Some(expiry) = ids.expiry {
+        uri.push_str(&format!("?17={}", encode_date(expiry)));
+    }
+    uri
+}
+
+/// Human-readable GS1-128 element string, e.g. `(01)09506000134352(17)270131(10)LOT1(21)SN1`.
+pub fn element_string(ids: &Gs1Identifiers) -> String {
+    elements(ids).into_iter().map(|(ai, value)| format!("({}){}", ai, value)).collect()
+}
+
+/// Barcode data for a GS1-128 symbol: element strings without brackets, with
+/// `GROUP_SEPARATOR` after every variable-length element but the last. The
+/// leading FNC1 is left to the barcode encoder.
+pub fn barcode_data(ids: &Gs1Identifiers) -> String {
+    let elements = elements(ids);
+    let mut data = String::new();
+    for (i, (ai, value)) in elements.iter().enumerate() {
+        data.push_str(ai);
+        data.push_str(value);
+        if fixed_length(ai).is_none() && i + 1 < elements.len() {
+            data.push(GROUP_SEPARATOR);
+        }
+    }
+    data
+}
+
+/// Labels for a line item: one per serialized unit, or a single lot-level
+/// label when no serials were recorded. `None` if the product has no GTIN.
+pub fn line_item_labels(resolver: &str, item: &LineItem) -> Option<Vec<Gs1Label>> {
+    let gtin = item.product.gtin.clone()?;
+    let base = Gs1Identifiers { gtin, lot: item.lot.clone(), expiry: item.expiry, serial: None };
+    
+    let units: Vec<Gs1Identifiers> = if item.serials.is_empty() {
+        vec![base]
+    } else {
+        item.serials
+            .iter()
+            .map(|serial| Gs1Identifiers { serial: Some(serial.clone()), ..base.clone() })
+            .collect()
+    };
+    Some(
+        units
+            .into_iter()
+            .map(|ids| Gs1Label {
+                digital_link: digital_link(resolver, &ids),
+                element_string: element_string(&ids),
+                barcode_data: barcode_data(&ids),
+                identifiers: ids,
+            })
+            .collect(),
+    )
+}
+
+/// Labels for every line item whose product has a GTIN, or `None` if none has one.
+pub fn shipment_labels(resolver: &str, shipment: &Shipment) -> Option<Vec<Gs1Label>> {
+    let labels: Vec<Vec<Gs1Label>> = shipment
+        .line_items
+        .iter()
+        .filter_map(|item| line_item_labels(resolver, item))
+        .collect();
+    (!labels.is_empty()).then(|| labels.into_iter().flatten().collect())
+}
+
+/// Reads identifiers from a Digital Link URI, a bracketed element string or
+/// raw barcode data (optionally prefixed with the `]C1` symbology identifier).
+pub fn parse(data: &str) -> Result<Gs1Identifiers, Gs1Error> {
+    let data = data.trim();
+    if data.starts_with("http://") || data.starts_with("https://") {
+        parse_digital_link(data)
+    } else if data.starts_with('(') {
+        parse_bracketed(data)
+    } else {
+        parse_barcode_data(data.strip_prefix("]C1").unwrap_or(data))
+    }
+}
+
+fn parse_digital_link(uri: &str) -> Result<Gs1Identifiers, Gs1Error> {
+    let malformed = || Gs1Error::Malformed(uri.to_string());
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let segments: Vec<&str> = path.split('/').collect();
+    let start = segments.iter().position(|segment| *segment == "01").ok_or_else(malformed)?;
+    
+    let mut elements = Vec::new();
+    for pair in segments[start..].chunks(2) {
+        match pair {
+            [ai, value] => elements.push((ai.to_string(), percent_decode(value).ok_or_else(malformed)?)),
+            _ => return Err(malformed()),
+        }
+    }
+    for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+        let (ai, value) = parameter.split_once('=').ok_or_else(malformed)?;
+        // Other query parameters (e.g. `linkType`) aren't identifiers
+        if ai.chars().all(|c| c.is_ascii_digit()) {
+            elements.push((ai.to_string(), percent_decode(value).ok_or_else(malformed)?));
+        }
+    }
+    identifiers(elements)
+}
+
+// Values containing `(` can't be told apart from the next AI here; scanners send raw data instead
+fn parse_bracketed(data: &str) -> Result<Gs1Identifiers, Gs1Error> {
+    let mut elements = Vec::new();
+    for element in data.split('(').skip(1) {
+        let (ai, value) = element.split_once(')').ok_or_else(|| Gs1Error::Malformed(data.to_string()))?;
+        elements.push((ai.to_string(), value.to_string()));
+    }
+    identifiers(elements)
+}
+
+fn parse_barcode_data(data: &str) -> Result<Gs1Identifiers, Gs1Error> {
+    let mut elements = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let ai = rest.get(..2).ok_or_else(|| Gs1Error::Malformed(data.to_string()))?;
+        let value = &rest[2..];
+        let len = match fixed_length(ai) {
+            Some(len) => len,
+            None if AIS.iter().any(|(known, _)| *known == ai) => value.find(GROUP_SEPARATOR).unwrap_or(value.len()),
+            None => return Err(Gs1Error::UnsupportedAi(ai.to_string())),
+        };
+        let value = value.get(..len).ok_or_else(|| Gs1Error::Malformed(data.to_string()))?;
+        elements.push((ai.to_string(), value.to_string()));
+        rest = rest[2 + len..].trim_start_matches(GROUP_SEPARATOR);
+    }
+    identifiers(elements)
+}
+
+fn identifiers(elements: Vec<(String, String)>) -> Result<Gs1Identifiers, Gs1Error> {
+    let mut gtin = None;
+    let mut ids = Gs1Identifiers { gtin: String::new(), lot: None, expiry: None, serial: None };
+    for (ai, value) in elements {
+        match ai.as_str() {
+            "01" => gtin = Some(normalize_gtin(&value)?),
+            "17" => ids.expiry = Some(decode_date(&value)?),
+            "10" => {
+                check_attribute("10", &value)?;
+                ids.lot = Some(value);
+            }
+            "21" => {
+                check_attribute("21", &value)?;
+                ids.serial = Some(value);
+            }
+            _ => return Err(Gs1Error::UnsupportedAi(ai)),
+        }
+    }
+    ids.gtin = gtin.ok_or_else(|| Gs1Error::Malformed("GTIN (01) is missing".to_string()))?;
+    Ok(ids)
+}
+
+/// Fixed-length elements first so only variable-length ones need separators.
+fn elements(ids: &Gs1Identifiers) -> Vec<(&'static str, String)> {
+    let mut elements = vec![("01", ids.gtin.clone())];
+    if let Some(expiry) = ids.expiry {
+        elements.push(("17", encode_date(expiry)));
+    }
+    if let Some(lot) = &ids.lot {
+        elements.push(("10", lot.clone()));
+    }
+    if let Some(serial) = &ids.serial {
+        elements.push(("21", serial.clone()));
+    }
+    elements
+}
+
+fn fixed_length(ai: &str) -> Option<usize> {
+    AIS.iter().find(|(known, _)| *known == ai).and_then(|(_, len)| *len)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn sample() -> Gs1Identifiers {
+        Gs1Identifiers {
+            gtin: "09506000134352".to_string(),
+            lot: Some("LOT/7".to_string()),
+            expiry: NaiveDate::from_ymd_opt(2027, 1, 31),
+            serial: Some("SN1001".to_string()),
+        }
+    }
+    
+    #[test]
+    fn gtins_are_check_digit_validated_and_padded() {
+        assert_eq!(normalize_gtin("9506000134352").unwrap(), "09506000134352");
+        assert_eq!(normalize_gtin("96385074").unwrap(), "00000096385074");
+        assert!(matches!(normalize_gtin("9506000134353"), Err(Gs1Error::InvalidCheckDigit(_))));
+        assert!(matches!(normalize_gtin("95060001343"), Err(Gs1Error::InvalidGtin(_))));
+    }
+    
+    #[test]
+    fn identifiers_round_trip_through_every_encoding() {
+        let ids = sample();
+        
+        let link = digital_link(DEFAULT_RESOLVER, &ids);
+        assert_eq!(link, "https://id.gs1.org/01/09506000134352/10/LOT%2F7/21/SN1001?17=270131");
+        assert_eq!(element_string(&ids), "(01)09506000134352(17)270131(10)LOT/7(21)SN1001");
+        assert_eq!(barcode_data(&ids), "01095060001343521727013110LOT/7\u{1d}21SN1001");
+        
+        for encoded in [link, element_string(&ids), format!("]C1{}", barcode_data(&ids))] {
+            assert_eq!(parse(&encoded).unwrap(), ids, "{}", encoded);
+        }
+    }
+    
+    #[test]
+    fn day_zero_means_end_of_month() {
+        assert_eq!(decode_date("280200").unwrap(), NaiveDate::from_ymd_opt(2028, 2, 29).unwrap());
+        assert!(decode_date("271301").is_err());
+    }
+}
@@ -1,21 +1,207 @@
-use actix_web::{web, HttpResponse, Result};
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Result};
 use std::sync::Arc;
-use chrono::Utc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast::error::RecvError;
 use uuid::Uuid;
+use validator::{Validate, ValidateArgs};
 
+use crate::epcis::{self, EpcisDocument};
+use crate::errors::ApiError;
+use crate::gs1;
+use crate::middleware;
 use crate::models::*;
+use crate::openapi::ErrorResponse;
 use crate::storage::Storage;
-use crate::services::{WeatherService, PasswordService};
+use crate::versions::ApiVersion;
+use crate::wire;
+use crate::services::{
+    WeatherService, PasswordService, DeviceKeyService, TelemetryService, ThermalService, WebhookService, VerificationService,
+    QrService, EventChainService, CertificateService, EscrowService, TraceabilityService, RecallService,
+};
 
-pub async fn get_products(storage: web::Data<Arc<Storage>>) -> Result<HttpResponse> {
-    let products = storage.get_products();
-    Ok(HttpResponse::Ok().json(ApiResponse::success(products)))
+const DEVICE_KEY_HEADER: &str = "X-Device-Key";
+
+/// Authenticates the device API key on the request, if one was sent.
+/// Returns `Ok(None)` for requests without a key so human callers keep working.
+fn authenticate_device(http_req: &HttpRequest, storage: &Storage) -> Result<Option<Device>, ApiError> {
+    let api_key = match http_req.headers().get(DEVICE_KEY_HEADER) {
+        Some(value) => value.to_str().unwrap_or_default(),
+        None => return Ok(None),
+    };
+    
+    match storage.touch_device_by_key(&DeviceKeyService::hash_key(api_key)) {
+        Some(device) => Ok(Some(device)),
+        None => Err(ApiError::Unauthorized("Invalid or revoked device key".to_string())),
+    }
+}
+
+/// Parses `If-Match` into the shipment versions it names. `*` and a missing
+/// header both mean "any version". Comparison is strong, so weak ETags are
+/// never matched: a header of only weak ETags fails every precondition.
+fn if_match_versions(http_req: &HttpRequest) -> Result<Option<Vec<u64>>, ApiError> {
+    let mut versions = Vec::new();
+    let mut present = false;
+    for value in http_req.headers().get_all(header::IF_MATCH) {
+        present = true;
+        let value = value.to_str().map_err(|_| ApiError::Validation("If-Match must be a list of ETags".to_string()))?;
+        for tag in value.split(',').map(str::trim).filter(|tag| !tag.is_empty()) {
+            if tag == "*" {
+                return Ok(None);
+            }
+            if tag.starts_with("W/") {
+                continue;
+            }
+            let version = tag
+                .trim_matches('"')
+                .parse()
+                .map_err(|_| ApiError::Validation("If-Match must list shipment version ETags".to_string()))?;
+            versions.push(version);
+        }
+    }
+    Ok(present.then_some(versions))
+}
+
+fn check_version(shipment: &Shipment, expected_versions: Option<&[u64]>) -> Result<(), ApiError> {
+    match expected_versions {
+        Some(versions) if !versions.contains(&shipment.version) => Err(ApiError::PreconditionFailed(format!(
+            "Shipment is at version {}, which If-Match does not name as a strong ETag",
+            shipment.version
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Appends a custody event and moves the shipment's status along with it.
+/// Outbound, only the carrier on the last leg can deliver.
+fn record_event(shipment: &mut Shipment, event: ShipmentEvent) -> Result<(), ApiError> {
+    let breached = !event.violations.is_empty();
+    let delivered = matches!(event.event_type, EventType::Delivery);
+    let timestamp = event.timestamp;
+    
+    // Events on the way back don't touch the outbound legs; delivery is to the manufacturer
+    if matches!(shipment.status, ShipmentStatus::Returning) {
+        EventChainService::append(shipment, event);
+        if breached {
+            compromise(shipment, timestamp);
+        }
+        if delivered {
+            complete_return(shipment, timestamp);
+        }
+        return Ok(());
+    }
+    
+    if delivered && shipment.current_leg() + 1 < shipment.legs.len() {
+        return Err(ApiError::Conflict("Only the last leg can deliver; hand the shipment off first".to_string()));
+    }
+    
+    // Whatever first moves the shipment starts the first leg
+    if let Some(leg) = shipment.legs.first_mut() {
+        leg.started_at.get_or_insert(timestamp);
+    }
+    if delivered {
+        if let Some(leg) = shipment.legs.last_mut() {
+            leg.ended_at = Some(timestamp);
+        }
+    }
+    EventChainService::append(shipment, event);
+    
+    // Update shipment status based on constraint validation, once the goods are on the move
+    if matches!(shipment.status, ShipmentStatus::Pending) {
+        shipment.status = ShipmentStatus::InTransit;
+    }
+    if breached && !matches!(shipment.status, ShipmentStatus::Compromised) {
+        compromise(shipment, timestamp);
+    } else if delivered && !matches!(shipment.status, ShipmentStatus::Compromised) {
+        shipment.status = ShipmentStatus::Delivered;
+        shipment.delivered_at = Some(Utc::now());
+    }
+    Ok(())
+}
+
+/// Marks the shipment compromised, blaming the leg in progress at `timestamp`.
+/// On the way back only the return is marked. Goods that haven't been picked
+/// up yet, already reached the consumer or came back are left be.
+fn compromise(shipment: &mut Shipment, timestamp: DateTime<Utc>) {
+    match shipment.status {
+        ShipmentStatus::InTransit => {
+            shipment.status = ShipmentStatus::Compromised;
+            shipment.compromised_leg = shipment.leg_at(timestamp);
+        }
+        ShipmentStatus::Returning => match shipment.return_details.as_mut() {
+            // An outbound budget overrun reported late isn't the return's fault
+            Some(details) if details.requested_at <= timestamp => details.cold_chain_intact = false,
+            _ => return,
+        },
+        _ => return,
+    }
+    shipment.compromised_at.get_or_insert(timestamp);
+}
+
+/// Settles the escrow once returned goods reach the manufacturer.
+fn complete_return(shipment: &mut Shipment, timestamp: DateTime<Utc>) {
+    let Some(mut details) = shipment.return_details.take() else {
+        return;
+    };
+    details.returned_at = Some(timestamp);
+    let refund = EscrowService::return_refund(shipment, &details);
+    shipment.return_details = Some(details);
+    
+    shipment.escrow_released = refund.amount_minor < shipment.escrow.amount_minor;
+    shipment.escrow_refund = Some(refund);
+    shipment.status = ShipmentStatus::Returned;
 }
 
+fn ensure_open(shipment: &Shipment) -> Result<(), ApiError> {
+    match shipment.status {
+        ShipmentStatus::Cancelled => Err(ApiError::Conflict("Shipment has been cancelled".to_string())),
+        ShipmentStatus::Returned => Err(ApiError::Conflict("Shipment has been returned".to_string())),
+        _ => Ok(()),
+    }
+}
+
+fn etag(shipment: &Shipment) -> header::ETag {
+    header::ETag(header::EntityTag::new_strong(shipment.version.to_string()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/products",
+    tag = "Products",
+    summary = "List products",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "A page of products", body = ApiResponse<Page<Product>>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+    )
+)]
+pub async fn get_products(
+    storage: web::Data<Arc<Storage>>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let page = storage.list_products(&query)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(page)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/products",
+    tag = "Products",
+    summary = "Register a product",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replay-safe retry key")),
+    request_body = RegisterProductRequest,
+    responses(
+        (status = 200, description = "The registered product", body = ApiResponse<Product>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 422, description = "Idempotency-Key reused for a different request", body = ErrorResponse),
+    )
+)]
 pub async fn register_product(
     storage: web::Data<Arc<Storage>>,
     req: web::Json<RegisterProductRequest>,
-) -> Result<HttpResponse> {
+) -> Result<HttpResponse, ApiError> {
+    req.validate_with_args(storage.get_ref())?;
+    
     let product = Product {
         id: format!("prod-{}", Uuid::new_v4()),
         name: req.name.clone(),
@@ -23,159 +209,1541 @@ pub async fn register_product(
         manufacturer: req.manufacturer.clone(),
         min_temperature: req.min_temperature,
         max_temperature: req.max_temperature,
+        excursion_budget_minutes: req.excursion_budget_minutes,
+        constraints: req.constraints.clone(),
         logistics_partner: req.logistics_partner.clone(),
+        gtin: req.gtin.as_deref().map(gs1::normalize_gtin).transpose()?,
+        unit_price: req.unit_price.clone(),
         registered_at: Utc::now(),
+        updated_at: None,
+        deleted_at: None,
     };
     
-    match storage.add_product(product) {
-        Ok(product) => Ok(HttpResponse::Ok().json(ApiResponse::success(product))),
-        Err(e) => Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+    let product = storage.add_product(product)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(product)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/products/{id}",
+    tag = "Products",
+    summary = "Get a product",
+    params(("id" = String, Path, description = "Product id")),
+    responses(
+        (status = 200, description = "The product", body = ApiResponse<Product>),
+        (status = 404, description = "Product not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_product(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let product_id = path.into_inner();
+    
+    let product = storage.get_product(&product_id).ok_or_else(|| ApiError::not_found("Product"))?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(product)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/products/{id}/lots",
+    tag = "Products",
+    summary = "List the shipped lots of a product",
+    params(("id" = String, Path, description = "Product id")),
+    responses(
+        (status = 200, description = "Lots in the order first shipped", body = ApiResponse<Vec<LotSummary>>),
+        (status = 404, description = "Product not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_product_lots(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let product_id = path.into_inner();
+    
+    storage.get_product(&product_id).ok_or_else(|| ApiError::not_found("Product"))?;
+    
+    let lots = TraceabilityService::lots(&product_id, &storage.get_shipments_with_product(&product_id));
+    Ok(HttpResponse::Ok().json(ApiResponse::success(lots)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/products/{id}/lots/{lot}",
+    tag = "Products",
+    summary = "Trace a lot to the shipments carrying it",
+    params(
+        ("id" = String, Path, description = "Product id"),
+        ("lot" = String, Path, description = "GS1 batch/lot number"),
+    ),
+    responses(
+        (status = 200, description = "The shipments carrying the lot", body = ApiResponse<LotTrace>),
+        (status = 404, description = "Product or lot not found", body = ErrorResponse),
+    )
+)]
+pub async fn trace_product_lot(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (product_id, lot) = path.into_inner();
+    
+    storage.get_product(&product_id).ok_or_else(|| ApiError::not_found("Product"))?;
+    
+    let trace = TraceabilityService::trace(&product_id, &lot, &storage.get_shipments_with_product(&product_id))
+        .ok_or_else(|| ApiError::not_found("Lot"))?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(trace)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/products/{id}",
+    tag = "Products",
+    summary = "Update a product's name or description",
+    params(("id" = String, Path, description = "Product id")),
+    request_body = UpdateProductRequest,
+    responses(
+        (status = 200, description = "The updated product", body = ApiResponse<Product>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 404, description = "Product not found", body = ErrorResponse),
+        (status = 409, description = "Product has been deleted", body = ErrorResponse),
+    )
+)]
+pub async fn update_product(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    req: web::Json<UpdateProductRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let product_id = path.into_inner();
+    
+    if storage.get_product(&product_id).is_none() {
+        return Err(ApiError::not_found("Product"));
     }
+    
+    req.validate()?;
+    let req = req.into_inner();
+    let product = storage.update_product(&product_id, req.name, req.description)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(product)))
 }
 
-pub async fn get_shipments(storage: web::Data<Arc<Storage>>) -> Result<HttpResponse> {
-    let shipments = storage.get_shipments();
-    Ok(HttpResponse::Ok().json(ApiResponse::success(shipments)))
+#[utoipa::path(
+    delete,
+    path = "/products/{id}",
+    tag = "Products",
+    summary = "Soft-delete a product",
+    params(("id" = String, Path, description = "Product id")),
+    responses(
+        (status = 200, description = "The deleted product", body = ApiResponse<Product>),
+        (status = 404, description = "Product not found", body = ErrorResponse),
+    )
+)]
+pub async fn delete_product(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let product_id = path.into_inner();
+    
+    let product = storage.delete_product(&product_id)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(product)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/shipments",
+    tag = "Shipments",
+    summary = "List shipments",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "A page of shipments", body = ApiResponse<Page<Shipment>>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+    )
+)]
+pub async fn get_shipments<V: ApiVersion>(
+    storage: web::Data<Arc<Storage>>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let page = storage.list_shipments(&query)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(page.map(V::Shipment::from))))
 }
 
-pub async fn fund_escrow(
+#[utoipa::path(
+    get,
+    path = "/shipments/{id}",
+    tag = "Shipments",
+    summary = "Get a shipment",
+    params(("id" = String, Path, description = "Shipment id")),
+    responses(
+        (status = 200, description = "The shipment, with its version as ETag", body = ApiResponse<Shipment>),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_shipment<V: ApiVersion>(
     storage: web::Data<Arc<Storage>>,
-    req: web::Json<FundEscrowRequest>,
-) -> Result<HttpResponse> {
-    let product = match storage.get_product(&req.product_id) {
-        Some(product) => product,
-        None => {
-            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Product not found".to_string())));
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let shipment = storage.get_shipment(&shipment_id).ok_or_else(|| ApiError::not_found("Shipment"))?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&shipment))
+        .json(ApiResponse::success(V::Shipment::from(shipment))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/shipments",
+    tag = "Shipments",
+    summary = "Fund escrow and create a shipment",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replay-safe retry key")),
+    request_body = FundEscrowRequest,
+    responses(
+        (status = 200, description = "The created shipment", body = ApiResponse<Shipment>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 404, description = "Product not found", body = ErrorResponse),
+        (status = 409, description = "Product has been deleted", body = ErrorResponse),
+        (status = 422, description = "Idempotency-Key reused for a different request", body = ErrorResponse),
+    )
+)]
+pub async fn fund_escrow<V: ApiVersion>(
+    storage: web::Data<Arc<Storage>>,
+    req: web::Json<V::FundEscrowRequest>,
+) -> Result<HttpResponse, ApiError> {
+    req.validate_with_args(storage.get_ref())?;
+    let req: FundEscrowRequest = req.into_inner().into();
+    
+    let mut line_items = Vec::with_capacity(req.line_items.len());
+    for item in req.line_items {
+        let product = storage.get_product(&item.product_id).ok_or_else(|| ApiError::not_found("Product"))?;
+        
+        if product.deleted_at.is_some() {
+            return Err(ApiError::Conflict(format!("Product {} has been deleted", product.id)));
+        }
+        let has_gs1_data = item.lot.is_some() || item.expiry.is_some() || !item.serials.is_empty();
+        if has_gs1_data && product.gtin.is_none() {
+            return Err(ApiError::Validation(format!("Lot, expiry and serials require a GTIN, which product {} lacks", product.id)));
+        }
+        
+        line_items.push(LineItem {
+            product_id: item.product_id,
+            unit_price: item.unit_price.or_else(|| product.unit_price.clone()),
+            product,
+            lot: item.lot,
+            expiry: item.expiry,
+            serials: item.serials,
+            quantity: item.quantity,
+            unit: item.unit,
+        });
+    }
+    
+    let today = Utc::now().date_naive();
+    for item in &line_items {
+        if let Some(recall) = storage.find_open_recall(item, today) {
+            return Err(ApiError::Conflict(format!("Product {} is under recall {}", item.product_id, recall.id)));
         }
+    }
+    
+    // A shipment has one manufacturer; without a chosen route, the first product's carrier takes it all the way
+    let first = line_items[0].product.clone();
+    if line_items.iter().any(|item| item.product.manufacturer != first.manufacturer) {
+        return Err(ApiError::Validation("All line items must come from the same manufacturer".to_string()));
+    }
+    let escrow = EscrowService::amount_for(&line_items, req.escrow)?;
+    let legs: Vec<Leg> = if req.legs.is_empty() {
+        vec![Leg::new(req.logistics_partner.unwrap_or_else(|| first.logistics_partner.clone()))]
+    } else {
+        req.legs.into_iter().map(Leg::new).collect()
     };
     
     let shipment = Shipment {
         id: format!("ship-{}", Uuid::new_v4()),
-        product_id: req.product_id.clone(),
-        product: product.clone(),
-        manufacturer: product.manufacturer,
-        logistics_partner: product.logistics_partner,
+        product_id: first.id.clone(),
+        manufacturer: first.manufacturer.clone(),
+        logistics_partner: legs[0].partner.clone(),
+        product: first,
         consumer: req.consumer.clone(),
         status: ShipmentStatus::Pending,
-        escrow_amount: req.escrow_amount,
+        escrow,
         escrow_released: false,
+        escrow_refund: None,
+        line_items,
         events: vec![],
+        recalls: vec![],
+        legs,
+        compromised_leg: None,
+        compromised_at: None,
+        cancellation: None,
+        return_details: None,
         created_at: Utc::now(),
         delivered_at: None,
         confirmed_at: None,
+        version: 0,
     };
     
-    match storage.add_shipment(shipment) {
-        Ok(shipment) => Ok(HttpResponse::Ok().json(ApiResponse::success(shipment))),
-        Err(e) => Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
-    }
+    let shipment = storage.add_shipment(shipment)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&shipment))
+        .json(ApiResponse::success(V::Shipment::from(shipment))))
 }
 
-pub async fn add_event(
+#[utoipa::path(
+    post,
+    path = "/shipments/{id}/events",
+    tag = "Shipments",
+    summary = "Record a custody event",
+    params(("id" = String, Path, description = "Shipment id"), ("X-Device-Key" = Option<String>, Header, description = "Device API key"), ("If-Match" = Option<String>, Header, description = "Expected shipment version"), ("Idempotency-Key" = Option<String>, Header, description = "Replay-safe retry key")),
+    request_body = AddEventRequest,
+    responses(
+        (status = 200, description = "The updated shipment", body = ApiResponse<Shipment>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Invalid device key", body = ErrorResponse),
+        (status = 403, description = "Device not assigned to this shipment", body = ErrorResponse),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+        (status = 409, description = "Concurrent modification, or delivery before the last leg", body = ErrorResponse),
+        (status = 412, description = "If-Match version mismatch", body = ErrorResponse),
+        (status = 422, description = "Idempotency-Key reused for a different request", body = ErrorResponse),
+        (status = 502, description = "Weather service unavailable", body = ErrorResponse),
+    )
+)]
+pub async fn add_event<V: ApiVersion>(
+    http_req: HttpRequest,
     storage: web::Data<Arc<Storage>>,
     path: web::Path<String>,
     req: web::Json<AddEventRequest>,
-) -> Result<HttpResponse> {
+) -> Result<HttpResponse, ApiError> {
     let shipment_id = path.into_inner();
     
-    let mut shipment = match storage.get_shipment(&shipment_id) {
-        Some(shipment) => shipment,
-        None => {
-            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Shipment not found".to_string())));
+    let device = authenticate_device(&http_req, &storage)?;
+    let expected_versions = if_match_versions(&http_req)?;
+    req.validate()?;
+    
+    let shipment = storage.get_shipment(&shipment_id).ok_or_else(|| ApiError::not_found("Shipment"))?;
+    
+    // Device keys are scoped to shipments carried by the device's owner
+    if let Some(device) = &device {
+        if device.owner != shipment.logistics_partner {
+            return Err(ApiError::Forbidden("Device is not assigned to this shipment".to_string()));
         }
+    }
+    let reporter = match &device {
+        Some(device) => device.id.clone(),
+        None => req.reporter.clone(),
     };
     
     // Verify temperature with weather API
-    let weather_data = match WeatherService::verify_temperature(&req.location, req.temperature).await {
-        Ok(data) => data,
-        Err(e) => {
+    let weather_data = WeatherService::verify_temperature(&storage.weather_url, &req.location, req.temperature)
+        .await
+        .map_err(|e| {
             log::error!("Weather verification failed: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Weather verification failed".to_string())));
-        }
-    };
+            ApiError::Upstream("Weather verification failed".to_string())
+        })?;
     
-    // Check if temperature is within acceptable range
-    let is_temperature_valid = weather_data.temperature >= shipment.product.min_temperature
-        && weather_data.temperature <= shipment.product.max_temperature;
+    // Check the verified temperature and any reported sensor values against the product's limits
+    let timestamp = Utc::now();
+    let violations = TelemetryService::check_reading(&shipment.limits(), &TelemetryReading {
+        timestamp,
+        temperature: Some(weather_data.temperature),
+        humidity: req.humidity,
+        shock: req.shock,
+        light: req.light,
+    });
+    let is_temperature_valid = !violations
+        .iter()
+        .any(|violation| violation.metric == EnvironmentalMetric::Temperature);
     
     let event = ShipmentEvent {
         id: format!("event-{}", Uuid::new_v4()),
-        timestamp: Utc::now(),
+        timestamp,
         location: req.location.clone(),
         temperature: req.temperature,
         verified_temperature: weather_data.temperature,
-        reporter: req.reporter.clone(),
+        reporter,
         event_type: req.event_type.clone(),
         is_temperature_valid,
+        humidity: req.humidity,
+        shock: req.shock,
+        light: req.light,
+        violations: violations.clone(),
+        coordinates: req.coordinates,
+        hash: String::new(),
     };
     
-    shipment.events.push(event.clone());
+    let shipment = storage.modify_shipment(&shipment_id, |shipment| {
+        check_version(shipment, expected_versions.as_deref())?;
+        ensure_open(shipment)?;
+        record_event(shipment, event.clone())
+    })?;
     
-    // Update shipment status based on temperature validation
-    if !is_temperature_valid && !matches!(shipment.status, ShipmentStatus::Compromised) {
-        shipment.status = ShipmentStatus::Compromised;
-    } else if matches!(req.event_type, EventType::Delivery) && !matches!(shipment.status, ShipmentStatus::Compromised) {
-        shipment.status = ShipmentStatus::Delivered;
-        shipment.delivered_at = Some(Utc::now());
-    } else if matches!(shipment.status, ShipmentStatus::Pending) {
-        shipment.status = ShipmentStatus::InTransit;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&shipment))
+        .json(ApiResponse::success(V::Shipment::from(shipment))))
+}
+
+#[utoipa::path(
+    put,
+    path = "/shipments/{id}/logistics-partner",
+    tag = "Shipments",
+    summary = "Assign a leg's logistics partner",
+    description = "Reassigns a leg until it starts: the first leg before pickup, later legs until either side acknowledges the handoff into them.",
+    params(("id" = String, Path, description = "Shipment id"), ("If-Match" = Option<String>, Header, description = "Expected shipment version")),
+    request_body = AssignPartnerRequest,
+    responses(
+        (status = 200, description = "The updated shipment", body = ApiResponse<Shipment>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+        (status = 409, description = "Leg has already started or the shipment is no longer outbound", body = ErrorResponse),
+        (status = 412, description = "If-Match version mismatch", body = ErrorResponse),
+    )
+)]
+pub async fn assign_logistics_partner<V: ApiVersion>(
+    http_req: HttpRequest,
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    req: web::Json<AssignPartnerRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let expected_versions = if_match_versions(&http_req)?;
+    req.validate_with_args(storage.get_ref())?;
+    
+    let shipment = storage.modify_shipment(&shipment_id, |shipment| {
+        check_version(shipment, expected_versions.as_deref())?;
+        if !matches!(shipment.status, ShipmentStatus::Pending | ShipmentStatus::InTransit | ShipmentStatus::Compromised) {
+            return Err(ApiError::Conflict("Carriers can't be reassigned once a shipment is delivered, cancelled or returning".to_string()));
+        }
+        let Some(leg) = shipment.legs.get(req.leg) else {
+            return Err(ApiError::Validation(format!("Shipment has no leg {}", req.leg)));
+        };
+        if leg.started_at.is_some() || leg.ended_at.is_some() {
+            return Err(ApiError::Conflict(format!("Leg {} has already started", req.leg)));
+        }
+        if req.leg > 0 && shipment.legs[req.leg - 1].handoff.is_some() {
+            return Err(ApiError::Conflict(format!("The handoff into leg {} is already under way", req.leg)));
+        }
+        let neighbours = [req.leg.checked_sub(1), Some(req.leg + 1)];
+        if neighbours.into_iter().flatten().filter_map(|index| shipment.legs.get(index)).any(|next| next.partner == req.logistics_partner) {
+            return Err(ApiError::Validation("Consecutive legs must have different logistics partners".to_string()));
+        }
+        
+        shipment.legs[req.leg].partner = req.logistics_partner.clone();
+        shipment.logistics_partner = shipment.legs[shipment.current_leg()].partner.clone();
+        Ok(())
+    })?;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&shipment))
+        .json(ApiResponse::success(V::Shipment::from(shipment))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/shipments/{id}/handoffs",
+    tag = "Shipments",
+    summary = "Acknowledge a handoff between legs",
+    description = "The partners of the ending and the next leg each acknowledge the handoff. Once both have, the next leg starts and its partner becomes the shipment's logistics partner.",
+    params(("id" = String, Path, description = "Shipment id")),
+    request_body = HandoffRequest,
+    responses(
+        (status = 200, description = "The updated shipment", body = ApiResponse<Shipment>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 403, description = "User is not a partner on either side of the handoff", body = ErrorResponse),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+        (status = 409, description = "Leg is not in progress, is the last leg or was already acknowledged", body = ErrorResponse),
+    )
+)]
+pub async fn acknowledge_handoff<V: ApiVersion>(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    req: web::Json<HandoffRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    req.validate_with_args(storage.get_ref())?;
+    
+    let shipment = storage.modify_shipment(&shipment_id, |shipment| {
+        if !matches!(shipment.status, ShipmentStatus::InTransit | ShipmentStatus::Compromised) {
+            return Err(ApiError::Conflict("Only shipments in transit can be handed off".to_string()));
+        }
+        if req.leg != shipment.current_leg() {
+            return Err(ApiError::Conflict(format!("Leg {} is not in progress", req.leg)));
+        }
+        let next_partner = match shipment.legs.get(req.leg + 1) {
+            Some(next) => next.partner.clone(),
+            None => return Err(ApiError::Conflict(format!("Leg {} is the last leg", req.leg))),
+        };
+        
+        let leg = &mut shipment.legs[req.leg];
+        let outgoing = leg.partner == req.user_id;
+        let incoming = next_partner == req.user_id;
+        if !outgoing && !incoming {
+            return Err(ApiError::Forbidden("User is not a partner on either side of this handoff".to_string()));
+        }
+        
+        let now = Utc::now();
+        let handoff = leg.handoff.get_or_insert_with(|| Handoff {
+            location: req.location.clone(),
+            released_at: None,
+            received_at: None,
+        });
+        let acknowledged = (outgoing && handoff.released_at.is_none()) || (incoming && handoff.received_at.is_none());
+        if !acknowledged {
+            return Err(ApiError::Conflict("Handoff was already acknowledged by this partner".to_string()));
+        }
+        if outgoing {
+            handoff.released_at.get_or_insert(now);
+        }
+        if incoming {
+            handoff.received_at.get_or_insert(now);
+        }
+        
+        if handoff.released_at.is_some() && handoff.received_at.is_some() {
+            leg.ended_at = Some(now);
+            shipment.legs[req.leg + 1].started_at = Some(now);
+            shipment.logistics_partner = next_partner;
+        }
+        Ok(())
+    })?;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&shipment))
+        .json(ApiResponse::success(V::Shipment::from(shipment))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/shipments/{id}/cancel",
+    tag = "Shipments",
+    summary = "Cancel a shipment before pickup",
+    description = "Refunds the whole escrow to the consumer.",
+    params(("id" = String, Path, description = "Shipment id"), ("If-Match" = Option<String>, Header, description = "Expected shipment version"), ("Idempotency-Key" = Option<String>, Header, description = "Replay-safe retry key")),
+    request_body = CancelShipmentRequest,
+    responses(
+        (status = 200, description = "The cancelled shipment", body = ApiResponse<Shipment>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 403, description = "Requester is not the shipment's manufacturer or consumer", body = ErrorResponse),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+        (status = 409, description = "Shipment has already been picked up", body = ErrorResponse),
+        (status = 412, description = "If-Match version mismatch", body = ErrorResponse),
+        (status = 422, description = "Idempotency-Key reused for a different request", body = ErrorResponse),
+    )
+)]
+pub async fn cancel_shipment<V: ApiVersion>(
+    http_req: HttpRequest,
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    req: web::Json<CancelShipmentRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let expected_versions = if_match_versions(&http_req)?;
+    req.validate()?;
+    
+    let shipment = storage.modify_shipment(&shipment_id, |shipment| {
+        check_version(shipment, expected_versions.as_deref())?;
+        if req.requested_by != shipment.manufacturer && req.requested_by != shipment.consumer {
+            return Err(ApiError::Forbidden("Only the shipment's manufacturer or consumer can cancel it".to_string()));
+        }
+        if !matches!(shipment.status, ShipmentStatus::Pending) {
+            return Err(ApiError::Conflict("Only shipments awaiting pickup can be cancelled; return it instead".to_string()));
+        }
+        
+        shipment.status = ShipmentStatus::Cancelled;
+        shipment.escrow_refund = Some(shipment.escrow.clone());
+        shipment.cancellation = Some(Cancellation {
+            reason: req.reason.clone(),
+            requested_by: req.requested_by.clone(),
+            cancelled_at: Utc::now(),
+        });
+        Ok(())
+    })?;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&shipment))
+        .json(ApiResponse::success(V::Shipment::from(shipment))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/shipments/{id}/return",
+    tag = "Shipments",
+    summary = "Send a shipment back to its manufacturer",
+    description = "Starts the return trip. Custody events recorded afterwards are the reverse leg, checked against the same limits; a delivery event hands the goods back and settles the escrow. The consumer gets a full refund unless they refused goods that were sound and not under recall, in which case a 10% restocking fee goes to the manufacturer.",
+    params(("id" = String, Path, description = "Shipment id"), ("If-Match" = Option<String>, Header, description = "Expected shipment version"), ("Idempotency-Key" = Option<String>, Header, description = "Replay-safe retry key")),
+    request_body = ReturnShipmentRequest,
+    responses(
+        (status = 200, description = "The returning shipment", body = ApiResponse<Shipment>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 403, description = "Requester is not the shipment's manufacturer or consumer", body = ErrorResponse),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+        (status = 409, description = "Shipment has not been picked up, or its escrow is settled", body = ErrorResponse),
+        (status = 412, description = "If-Match version mismatch", body = ErrorResponse),
+        (status = 422, description = "Idempotency-Key reused for a different request", body = ErrorResponse),
+    )
+)]
+pub async fn return_shipment<V: ApiVersion>(
+    http_req: HttpRequest,
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    req: web::Json<ReturnShipmentRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let expected_versions = if_match_versions(&http_req)?;
+    req.validate()?;
+    
+    let shipment = storage.modify_shipment(&shipment_id, |shipment| {
+        check_version(shipment, expected_versions.as_deref())?;
+        if req.requested_by != shipment.manufacturer && req.requested_by != shipment.consumer {
+            return Err(ApiError::Forbidden("Only the shipment's manufacturer or consumer can return it".to_string()));
+        }
+        match shipment.status {
+            ShipmentStatus::InTransit | ShipmentStatus::Compromised | ShipmentStatus::Delivered => {}
+            ShipmentStatus::Pending => {
+                return Err(ApiError::Conflict("Shipment has not been picked up; cancel it instead".to_string()));
+            }
+            _ => return Err(ApiError::Conflict("Shipment can no longer be returned".to_string())),
+        }
+        
+        shipment.return_details = Some(ReturnDetails {
+            reason: req.reason,
+            details: req.details.clone(),
+            requested_by: req.requested_by.clone(),
+            requested_at: Utc::now(),
+            returned_from: shipment.status.clone(),
+            returned_at: None,
+            cold_chain_intact: true,
+        });
+        shipment.status = ShipmentStatus::Returning;
+        Ok(())
+    })?;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&shipment))
+        .json(ApiResponse::success(V::Shipment::from(shipment))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/shipments/{id}/confirm",
+    tag = "Shipments",
+    summary = "Confirm delivery and release escrow",
+    params(("id" = String, Path, description = "Shipment id"), ("If-Match" = Option<String>, Header, description = "Expected shipment version"), ("Idempotency-Key" = Option<String>, Header, description = "Replay-safe retry key")),
+    responses(
+        (status = 200, description = "The delivered shipment", body = ApiResponse<Shipment>),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+        (status = 409, description = "Shipment cannot be confirmed or its escrow is frozen by a recall", body = ErrorResponse),
+        (status = 412, description = "If-Match version mismatch", body = ErrorResponse),
+        (status = 422, description = "Idempotency-Key reused for a different request", body = ErrorResponse),
+    )
+)]
+pub async fn confirm_delivery<V: ApiVersion>(
+    http_req: HttpRequest,
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let expected_versions = if_match_versions(&http_req)?;
+    
+    let shipment = storage.modify_shipment(&shipment_id, |shipment| {
+        check_version(shipment, expected_versions.as_deref())?;
+        if !matches!(shipment.status, ShipmentStatus::Delivered) {
+            return Err(ApiError::Conflict("Shipment must be delivered before confirmation".to_string()));
+        }
+        if let Some(recall_id) = shipment.recalls.first() {
+            return Err(ApiError::Conflict(format!("Escrow is frozen by recall {}", recall_id)));
+        }
+        
+        // Release escrow funds
+        shipment.escrow_released = true;
+        shipment.status = ShipmentStatus::Confirmed;
+        shipment.confirmed_at = Some(Utc::now());
+        Ok(())
+    })?;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&shipment))
+        .json(ApiResponse::success(V::Shipment::from(shipment))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/shipments/{id}/telemetry",
+    tag = "Telemetry",
+    summary = "Ingest a batch of sensor readings",
+    params(("id" = String, Path, description = "Shipment id"), ("X-Device-Key" = Option<String>, Header, description = "Device API key")),
+    request_body = TelemetryBatchRequest,
+    responses(
+        (status = 200, description = "Ingestion result", body = ApiResponse<TelemetryBatchResponse>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Invalid device key", body = ErrorResponse),
+        (status = 403, description = "Device not assigned to this shipment", body = ErrorResponse),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+        (status = 409, description = "Shipment has been cancelled or returned", body = ErrorResponse),
+    )
+)]
+pub async fn ingest_telemetry(
+    http_req: HttpRequest,
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    req: web::Json<TelemetryBatchRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let device = authenticate_device(&http_req, &storage)?;
+    req.validate()?;
+    
+    let mut shipment = storage.get_shipment(&shipment_id).ok_or_else(|| ApiError::not_found("Shipment"))?;
+    
+    if let Some(device) = &device {
+        if device.owner != shipment.logistics_partner {
+            return Err(ApiError::Forbidden("Device is not assigned to this shipment".to_string()));
+        }
+    }
+    ensure_open(&shipment)?;
+    
+    let excursions = TelemetryService::evaluate(&shipment.limits(), &req.readings);
+    let accepted = storage.add_telemetry(&shipment_id, &req.readings);
+    
+    // Temperature excursions only compromise the shipment once the product's budget is spent;
+    // the leg carrying the goods at that moment, or at the first other breach, is blamed
+    let summary = ThermalService::summarize(&shipment_id, &shipment.limits(), &storage.get_telemetry(&shipment_id));
+    let since = [summary.budget_exceeded_at, TelemetryService::first_immediate_violation(&excursions)]
+        .into_iter()
+        .flatten()
+        .min();
+    if let Some(since) = since {
+        if matches!(shipment.status, ShipmentStatus::InTransit | ShipmentStatus::Returning) {
+            shipment = storage.modify_shipment(&shipment_id, |shipment| {
+                compromise(shipment, since);
+                Ok(())
+            })?;
+        }
     }
     
-    match storage.update_shipment(shipment.clone()) {
-        Ok(_) => Ok(HttpResponse::Ok().json(ApiResponse::success(shipment))),
-        Err(e) => Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+    Ok(HttpResponse::Ok().json(ApiResponse::success(TelemetryBatchResponse {
+        accepted,
+        excursions,
+        status: shipment.status,
+    })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/shipments/{id}/telemetry",
+    tag = "Telemetry",
+    summary = "Read stored telemetry",
+    params(("id" = String, Path, description = "Shipment id"), TelemetryQuery),
+    responses(
+        (status = 200, description = "Readings in time order", body = ApiResponse<Vec<TelemetryReading>>),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_telemetry(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    query: web::Query<TelemetryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    if storage.get_shipment(&shipment_id).is_none() {
+        return Err(ApiError::not_found("Shipment"));
+    }
+    
+    let readings: Vec<TelemetryReading> = storage
+        .get_telemetry(&shipment_id)
+        .into_iter()
+        .filter(|reading| query.from.is_none_or(|from| reading.timestamp >= from))
+        .filter(|reading| query.to.is_none_or(|to| reading.timestamp <= to))
+        .collect();
+    Ok(HttpResponse::Ok().json(ApiResponse::success(readings)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/shipments/{id}/thermal-summary",
+    tag = "Telemetry",
+    summary = "Summarize temperature exposure",
+    params(("id" = String, Path, description = "Shipment id")),
+    responses(
+        (status = 200, description = "Thermal summary", body = ApiResponse<ThermalSummary>),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_thermal_summary(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let shipment = storage.get_shipment(&shipment_id).ok_or_else(|| ApiError::not_found("Shipment"))?;
+    
+    let summary = ThermalService::summarize(&shipment_id, &shipment.limits(), &storage.get_telemetry(&shipment_id));
+    Ok(HttpResponse::Ok().json(ApiResponse::success(summary)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/shipments/{id}/qr",
+    tag = "Shipments",
+    summary = "Render a verification QR code",
+    description = "Encodes an opaque verification URL for the shipment, not the shipment itself. \
+                   Anyone scanning it can check the shipment without an account.",
+    params(("id" = String, Path, description = "Shipment id"), QrQuery),
+    responses(
+        (status = 200, description = "QR code image", content(
+            (String = "image/png"),
+            (String = "image/svg+xml"),
+        )),
+        (status = 400, description = "Invalid format or size", body = ErrorResponse),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_shipment_qr(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    query: web::Query<QrQuery>,
+) -> Result<HttpResponse, ApiError> {
+    query.validate()?;
+    let shipment_id = path.into_inner();
+    
+    if storage.get_shipment(&shipment_id).is_none() {
+        return Err(ApiError::not_found("Shipment"));
+    }
+    
+    let token = VerificationService::issue_token(&storage.verification_key, &shipment_id);
+    let url = format!("{}/api/verify/{}", storage.public_url, token);
+    
+    let (image, content_type) = QrService::render(&url, query.format, query.size.unwrap_or(QrService::DEFAULT_SIZE))?;
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((header::CACHE_CONTROL, "public, max-age=86400"))
+        .body(image))
+}
+
+#[utoipa::path(
+    get,
+    path = "/shipments/{id}/gs1",
+    tag = "GS1",
+    summary = "Generate GS1 label data",
+    description = "One label per serialized unit (SGTIN), or one lot-level label when the shipment has no serials.",
+    params(("id" = String, Path, description = "Shipment id")),
+    responses(
+        (status = 200, description = "Digital Link URIs and GS1-128 element strings", body = ApiResponse<Vec<Gs1Label>>),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+        (status = 409, description = "Product has no GTIN", body = ErrorResponse),
+    )
+)]
+pub async fn get_shipment_gs1(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let shipment = storage.get_shipment(&shipment_id).ok_or_else(|| ApiError::not_found("Shipment"))?;
+    
+    let labels = gs1::shipment_labels(gs1::DEFAULT_RESOLVER, &shipment)
+        .ok_or_else(|| ApiError::Conflict("Product has no GTIN".to_string()))?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(labels)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/shipments/{id}/epcis",
+    tag = "EPCIS",
+    summary = "Export the shipment as EPCIS 2.0",
+    description = "Shipment creation and each custody event as EPCIS `ObjectEvent`s with CBV business steps, \
+                   dispositions and sensor reports. Goods are identified by GS1 Digital Link URIs when the product has a GTIN.",
+    params(("id" = String, Path, description = "Shipment id")),
+    responses(
+        (status = 200, description = "EPCIS 2.0 JSON-LD document", content_type = "application/ld+json", body = serde_json::Value),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+    )
+)]
+pub async fn export_epcis(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let shipment = storage.get_shipment(&shipment_id).ok_or_else(|| ApiError::not_found("Shipment"))?;
+    Ok(HttpResponse::Ok()
+        .content_type(epcis::MEDIA_TYPE)
+        .json(epcis::export(&shipment)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/shipments/{id}/epcis",
+    tag = "EPCIS",
+    summary = "Import custody events from EPCIS 2.0",
+    description = "Creates a custody event for every `ObjectEvent` observed with a shipping, transporting or receiving \
+                   business step, checked against the product's limits. Events the shipment already has are skipped; \
+                   new ones must not predate its last recorded event.",
+    params(("id" = String, Path, description = "Shipment id"), ("X-Device-Key" = Option<String>, Header, description = "Device API key"), ("If-Match" = Option<String>, Header, description = "Expected shipment version")),
+    request_body(content = serde_json::Value, content_type = "application/ld+json", description = "EPCIS 2.0 JSON-LD document"),
+    responses(
+        (status = 200, description = "The updated shipment", body = ApiResponse<Shipment>),
+        (status = 400, description = "Invalid EPCIS document, or events older than the shipment's last", body = ErrorResponse),
+        (status = 401, description = "Invalid device key", body = ErrorResponse),
+        (status = 403, description = "Device not assigned to this shipment", body = ErrorResponse),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+        (status = 409, description = "Concurrent modification, or delivery before the last leg", body = ErrorResponse),
+        (status = 412, description = "If-Match version mismatch", body = ErrorResponse),
+    )
+)]
+pub async fn import_epcis<V: ApiVersion>(
+    http_req: HttpRequest,
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    document: web::Json<EpcisDocument>,
+) -> Result<HttpResponse, ApiError> {
+    let shipment_id = path.into_inner();
+    
+    let device = authenticate_device(&http_req, &storage)?;
+    let expected_versions = if_match_versions(&http_req)?;
+    
+    let shipment = storage.get_shipment(&shipment_id).ok_or_else(|| ApiError::not_found("Shipment"))?;
+    
+    if let Some(device) = &device {
+        if device.owner != shipment.logistics_partner {
+            return Err(ApiError::Forbidden("Device is not assigned to this shipment".to_string()));
+        }
     }
+    
+    let events = epcis::import(&shipment, document.into_inner())?;
+    
+    let shipment = storage.modify_shipment(&shipment_id, |shipment| {
+        check_version(shipment, expected_versions.as_deref())?;
+        ensure_open(shipment)?;
+        for event in &events {
+            // A concurrent import may have added the same events since they were parsed
+            if shipment.events.iter().any(|existing| existing.id == event.id) {
+                continue;
+            }
+            // ...or an event since, which the imported ones would then be recorded after
+            if shipment.events.last().is_some_and(|last| event.timestamp < last.timestamp) {
+                return Err(ApiError::Conflict("Shipment recorded newer events while the document was imported".to_string()));
+            }
+            record_event(shipment, event.clone())?;
+        }
+        Ok(())
+    })?;
+    
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&shipment))
+        .json(ApiResponse::success(V::Shipment::from(shipment))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/gs1/parse",
+    tag = "GS1",
+    summary = "Parse scanned GS1 data",
+    request_body = Gs1ParseRequest,
+    responses(
+        (status = 200, description = "Decoded identifiers", body = ApiResponse<Gs1Identifiers>),
+        (status = 400, description = "Invalid or unsupported GS1 data", body = ErrorResponse),
+    )
+)]
+pub async fn parse_gs1(
+    req: web::Json<Gs1ParseRequest>,
+) -> Result<HttpResponse, ApiError> {
+    req.validate()?;
+    
+    let identifiers = gs1::parse(&req.data)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(identifiers)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/verify/{token}",
+    tag = "Verification",
+    summary = "Verify a shipment from its QR code",
+    description = "Public and unauthenticated. Returns a redacted summary for a token issued by `GET /shipments/{id}/qr`.",
+    params(("token" = String, Path, description = "Opaque verification token")),
+    responses(
+        (status = 200, description = "Public shipment summary", body = ApiResponse<VerificationSummary>),
+        (status = 404, description = "Unknown or forged token", body = ErrorResponse),
+    )
+)]
+pub async fn verify_shipment(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let token = path.into_inner();
+    
+    // Forged tokens and deleted shipments look the same, so tokens can't be probed
+    let shipment = VerificationService::verify_token(&storage.verification_key, &token)
+        .and_then(|shipment_id| storage.get_shipment(&shipment_id))
+        .ok_or_else(|| ApiError::not_found("Shipment"))?;
+    
+    let manufacturer = storage.get_user(&shipment.manufacturer);
+    let summary = VerificationService::summarize(&shipment, manufacturer.as_ref());
+    Ok(HttpResponse::Ok().json(ApiResponse::success(summary)))
 }
 
-pub async fn confirm_delivery(
+#[utoipa::path(
+    get,
+    path = "/shipments/{id}/certificate",
+    tag = "Verification",
+    summary = "Issue a signed shipment certificate",
+    description = "The certificate carries the public summary and the event hash-chain head, and can be checked \
+                   offline against the key from `GET /certificates/key`, e.g. with `verichain verify-cert`.",
+    params(("id" = String, Path, description = "Shipment id")),
+    responses(
+        (status = 200, description = "Certificate and its decoded claims", body = ApiResponse<ShipmentCertificate>),
+        (status = 404, description = "Shipment not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_shipment_certificate(
     storage: web::Data<Arc<Storage>>,
     path: web::Path<String>,
-) -> Result<HttpResponse> {
+) -> Result<HttpResponse, ApiError> {
     let shipment_id = path.into_inner();
     
-    let mut shipment = match storage.get_shipment(&shipment_id) {
-        Some(shipment) => shipment,
-        None => {
-            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Shipment not found".to_string())));
+    let shipment = storage.get_shipment(&shipment_id).ok_or_else(|| ApiError::not_found("Shipment"))?;
+    
+    let manufacturer = storage.get_user(&shipment.manufacturer);
+    let certificate = CertificateService::issue(&storage.signing_key, &shipment, manufacturer.as_ref());
+    Ok(HttpResponse::Ok().json(ApiResponse::success(certificate)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/certificates/key",
+    tag = "Verification",
+    summary = "Get the certificate signing public key",
+    responses(
+        (status = 200, description = "Public key", body = ApiResponse<CertificateKey>),
+    )
+)]
+pub async fn get_certificate_key(
+    storage: web::Data<Arc<Storage>>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(ApiResponse::success(CertificateService::public_key(&storage.signing_key))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/shipments/stream",
+    tag = "Shipments",
+    summary = "Stream shipment changes as server-sent events",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "text/event-stream of ShipmentUpdate frames", body = ShipmentUpdate, content_type = "text/event-stream"),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+    )
+)]
+pub async fn stream_shipment_updates<V: ApiVersion>(
+    http_req: HttpRequest,
+    storage: web::Data<Arc<Storage>>,
+    query: web::Query<StreamQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let camel = middleware::names_camel_json(http_req.headers(), header::ACCEPT);
+    
+    if query.user_id.is_some() != query.role.is_some() {
+        return Err(ApiError::Validation("user_id and role must be given together".to_string()));
+    }
+    
+    let receiver = storage.subscribe();
+    let keep_alive = tokio::time::interval(Duration::from_secs(15));
+    
+    // Server-sent events: one frame per matching update, plus periodic comments
+    // so proxies don't drop idle connections
+    let stream = futures_util::stream::unfold((receiver, keep_alive, query), move |(mut receiver, mut keep_alive, query)| async move {
+        let frame = loop {
+            tokio::select! {
+                update = receiver.recv() => match update {
+                    Ok(update) if query.matches(&update) => {
+                        let name = update.change.name();
+                        let data = serde_json::to_value(V::ShipmentUpdate::from(update)).unwrap_or_default();
+                        let data = if camel { wire::to_camel(data) } else { data };
+                        break format!("event: {}\ndata: {}\n\n", name, data);
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        break format!("event: lagged\ndata: {{\"skipped\":{}}}\n\n", skipped);
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = keep_alive.tick() => break ": keep-alive\n\n".to_string(),
+            }
+        };
+        Some((Ok::<_, actix_web::Error>(web::Bytes::from(frame)), (receiver, keep_alive, query)))
+    });
+    
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header((header::VARY, "Accept"))
+        .streaming(stream))
+}
+
+#[utoipa::path(
+    post,
+    path = "/recalls",
+    tag = "Recalls",
+    summary = "Recall a product or lot",
+    description = "Flags every shipment carrying the recalled goods, notifies its participants and freezes its escrow until the recall is closed. New shipments of the goods are refused while it is open.",
+    request_body = CreateRecallRequest,
+    responses(
+        (status = 200, description = "The recall and where the affected goods are", body = ApiResponse<RecallReport>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 403, description = "Issuer is not the product's manufacturer", body = ErrorResponse),
+        (status = 404, description = "Product not found", body = ErrorResponse),
+    )
+)]
+pub async fn create_recall(
+    storage: web::Data<Arc<Storage>>,
+    req: web::Json<CreateRecallRequest>,
+) -> Result<HttpResponse, ApiError> {
+    req.validate_with_args(storage.get_ref())?;
+    let req = req.into_inner();
+    
+    let product = storage.get_product(&req.product_id).ok_or_else(|| ApiError::not_found("Product"))?;
+    if product.manufacturer != req.issued_by {
+        return Err(ApiError::Forbidden("Only the product's manufacturer can recall it".to_string()));
+    }
+    if req.lot.is_some() && product.gtin.is_none() {
+        return Err(ApiError::Validation(format!("Product {} has no GTIN, so it has no lots", product.id)));
+    }
+    
+    let mut recall = Recall {
+        id: format!("recall-{}", Uuid::new_v4()),
+        product_id: req.product_id,
+        lot: req.lot,
+        shipped_from: req.shipped_from,
+        shipped_until: req.shipped_until,
+        reason: req.reason,
+        issued_by: req.issued_by,
+        status: RecallStatus::Open,
+        shipment_ids: vec![],
+        created_at: Utc::now(),
+        closed_at: None,
+    };
+    let shipments: Vec<Shipment> = storage
+        .get_shipments_with_product(&recall.product_id)
+        .into_iter()
+        .filter(|shipment| recall.covered_items(shipment).next().is_some())
+        .collect();
+    recall.shipment_ids = shipments.iter().map(|shipment| shipment.id.clone()).collect();
+    
+    // Flag every shipment before storing the recall, and unflag them again if
+    // one fails, so no recall is left covering unflagged shipments. Storing the
+    // flag notifies each shipment's participants.
+    let mut flagged = Vec::with_capacity(shipments.len());
+    for shipment in &shipments {
+        let result = storage.modify_shipment(&shipment.id, |shipment| {
+            shipment.recalls.push(recall.id.clone());
+            Ok(())
+        });
+        match result {
+            Ok(shipment) => flagged.push(shipment),
+            Err(err) => {
+                for shipment in &flagged {
+                    let unflagged = storage.modify_shipment(&shipment.id, |shipment| {
+                        shipment.recalls.retain(|id| *id != recall.id);
+                        Ok(())
+                    });
+                    if let Err(e) = unflagged {
+                        log::error!("Failed to unflag shipment {} from abandoned recall {}: {}", shipment.id, recall.id, e);
+                    }
+                }
+                return Err(err);
+            }
         }
+    }
+    let recall = storage.add_recall(recall);
+    
+    Ok(HttpResponse::Ok().json(ApiResponse::success(RecallService::report(recall, &flagged))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/recalls",
+    tag = "Recalls",
+    summary = "List recalls",
+    responses(
+        (status = 200, description = "Recalls, newest first", body = ApiResponse<Vec<Recall>>),
+    )
+)]
+pub async fn get_recalls(storage: web::Data<Arc<Storage>>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(ApiResponse::success(storage.list_recalls())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/recalls/{id}",
+    tag = "Recalls",
+    summary = "Report where recalled goods are now",
+    params(("id" = String, Path, description = "Recall id")),
+    responses(
+        (status = 200, description = "The recall and the current custodian of each affected shipment", body = ApiResponse<RecallReport>),
+        (status = 404, description = "Recall not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_recall(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let recall_id = path.into_inner();
+    
+    let recall = storage.get_recall(&recall_id).ok_or_else(|| ApiError::not_found("Recall"))?;
+    let shipments: Vec<Shipment> = recall.shipment_ids.iter().filter_map(|id| storage.get_shipment(id)).collect();
+    Ok(HttpResponse::Ok().json(ApiResponse::success(RecallService::report(recall, &shipments))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/recalls/{id}/close",
+    tag = "Recalls",
+    summary = "Close a recall",
+    description = "Unfreezes the escrow of the affected shipments, unless another open recall still covers them.",
+    params(("id" = String, Path, description = "Recall id")),
+    responses(
+        (status = 200, description = "The closed recall", body = ApiResponse<RecallReport>),
+        (status = 404, description = "Recall not found", body = ErrorResponse),
+        (status = 409, description = "Recall is already closed", body = ErrorResponse),
+    )
+)]
+pub async fn close_recall(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let recall_id = path.into_inner();
+    
+    let recall = storage.get_recall(&recall_id).ok_or_else(|| ApiError::not_found("Recall"))?;
+    if recall.status == RecallStatus::Closed {
+        return Err(ApiError::Conflict("Recall is already closed".to_string()));
+    }
+    
+    // Unfreeze the shipments first, so a failure part way can be retried
+    let mut shipments = Vec::with_capacity(recall.shipment_ids.len());
+    for shipment_id in &recall.shipment_ids {
+        shipments.push(storage.modify_shipment(shipment_id, |shipment| {
+            shipment.recalls.retain(|id| *id != recall.id);
+            Ok(())
+        })?);
+    }
+    let recall = storage.close_recall(&recall_id)?;
+    
+    Ok(HttpResponse::Ok().json(ApiResponse::success(RecallService::report(recall, &shipments))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "Users",
+    summary = "List users",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "A page of users", body = ApiResponse<Page<User>>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+    )
+)]
+pub async fn get_users(
+    storage: web::Data<Arc<Storage>>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let page = storage.list_users(&query)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(page)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}",
+    tag = "Users",
+    summary = "Get a user",
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "The user", body = ApiResponse<User>),
+        (status = 404, description = "User not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_user(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = path.into_inner();
+    
+    let user = storage.get_user(&user_id).ok_or_else(|| ApiError::not_found("User"))?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(user)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}/shipments",
+    tag = "Users",
+    summary = "List shipments involving a user",
+    params(("id" = String, Path, description = "User id"), ListQuery),
+    responses(
+        (status = 200, description = "A page of shipments", body = ApiResponse<Page<Shipment>>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+    )
+)]
+pub async fn get_user_shipments<V: ApiVersion>(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = path.into_inner();
+    
+    let page = storage.list_shipments_by_user(&user_id, &query)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(page.map(V::Shipment::from))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/devices",
+    tag = "Devices",
+    summary = "Register a logistics device",
+    request_body = RegisterDeviceRequest,
+    responses(
+        (status = 200, description = "The device and its API key", body = ApiResponse<DeviceCredentials>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+    )
+)]
+pub async fn register_device(
+    storage: web::Data<Arc<Storage>>,
+    req: web::Json<RegisterDeviceRequest>,
+) -> Result<HttpResponse, ApiError> {
+    req.validate_with_args(storage.get_ref())?;
+    
+    let (api_key, key_prefix, key_hash) = DeviceKeyService::generate_key();
+    let device = Device {
+        id: format!("dev-{}", Uuid::new_v4()),
+        name: req.name.clone(),
+        owner: req.owner.clone(),
+        key_prefix,
+        key_hash,
+        status: DeviceStatus::Active,
+        created_at: Utc::now(),
+        rotated_at: None,
+        revoked_at: None,
+        last_seen_at: None,
     };
     
-    if !matches!(shipment.status, ShipmentStatus::Delivered) {
-        return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Shipment must be delivered before confirmation".to_string())));
+    let device = storage.add_device(device)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(DeviceCredentials { device, api_key })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}/devices",
+    tag = "Devices",
+    summary = "List a user's devices",
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "Devices owned by the user", body = ApiResponse<Vec<Device>>),
+    )
+)]
+pub async fn get_user_devices(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = path.into_inner();
+    
+    let devices = storage.get_devices_by_owner(&user_id);
+    Ok(HttpResponse::Ok().json(ApiResponse::success(devices)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/devices/{id}/rotate",
+    tag = "Devices",
+    summary = "Rotate a device API key",
+    params(("id" = String, Path, description = "Device id")),
+    responses(
+        (status = 200, description = "The device and its new API key", body = ApiResponse<DeviceCredentials>),
+        (status = 404, description = "Device not found", body = ErrorResponse),
+        (status = 409, description = "Device has been revoked", body = ErrorResponse),
+    )
+)]
+pub async fn rotate_device_key(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let device_id = path.into_inner();
+    
+    if storage.get_device(&device_id).is_none() {
+        return Err(ApiError::not_found("Device"));
     }
     
-    // Release escrow funds
-    shipment.escrow_released = true;
-    shipment.status = ShipmentStatus::Confirmed;
-    shipment.confirmed_at = Some(Utc::now());
+    let (api_key, key_prefix, key_hash) = DeviceKeyService::generate_key();
+    let device = storage.rotate_device_key(&device_id, key_prefix, key_hash)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(DeviceCredentials { device, api_key })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/devices/{id}/revoke",
+    tag = "Devices",
+    summary = "Revoke a device",
+    params(("id" = String, Path, description = "Device id")),
+    responses(
+        (status = 200, description = "The revoked device", body = ApiResponse<Device>),
+        (status = 404, description = "Device not found", body = ErrorResponse),
+    )
+)]
+pub async fn revoke_device(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let device_id = path.into_inner();
     
-    match storage.update_shipment(shipment.clone()) {
-        Ok(_) => Ok(HttpResponse::Ok().json(ApiResponse::success(shipment))),
-        Err(e) => Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+    if storage.get_device(&device_id).is_none() {
+        return Err(ApiError::not_found("Device"));
     }
+    
+    let device = storage.revoke_device(&device_id)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(device)))
 }
 
-pub async fn get_users(storage: web::Data<Arc<Storage>>) -> Result<HttpResponse> {
-    let users = storage.get_users();
-    Ok(HttpResponse::Ok().json(ApiResponse::success(users)))
+#[utoipa::path(
+    post,
+    path = "/users/{id}/webhooks",
+    tag = "Webhooks",
+    summary = "Subscribe to webhook events",
+    params(("id" = String, Path, description = "User id")),
+    request_body = CreateWebhookRequest,
+    responses(
+        (status = 200, description = "The subscription and its signing secret", body = ApiResponse<WebhookCredentials>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+    )
+)]
+pub async fn create_webhook(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+    req: web::Json<CreateWebhookRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = path.into_inner();
+    
+    req.validate()?;
+    
+    let secret = WebhookService::generate_secret();
+    let subscription = WebhookSubscription {
+        id: format!("wh-{}", Uuid::new_v4()),
+        user_id,
+        url: req.url.clone(),
+        events: req.events.clone(),
+        secret: secret.clone(),
+        created_at: Utc::now(),
+    };
+    
+    let subscription = storage.add_webhook(subscription)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(WebhookCredentials { subscription, secret })))
 }
 
-pub async fn get_user_shipments(
+#[utoipa::path(
+    get,
+    path = "/users/{id}/webhooks",
+    tag = "Webhooks",
+    summary = "List a user's webhook subscriptions",
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "Subscriptions", body = ApiResponse<Vec<WebhookSubscription>>),
+    )
+)]
+pub async fn get_user_webhooks(
     storage: web::Data<Arc<Storage>>,
     path: web::Path<String>,
-    query: web::Query<std::collections::HashMap<String, String>>,
-) -> Result<HttpResponse> {
+) -> Result<HttpResponse, ApiError> {
     let user_id = path.into_inner();
-    let role = query.get("role").unwrap_or(&"".to_string()).clone();
     
-    let shipments = storage.get_shipments_by_user(&user_id, &role);
-    Ok(HttpResponse::Ok().json(ApiResponse::success(shipments)))
+    let webhooks = storage.get_webhooks_by_user(&user_id);
+    Ok(HttpResponse::Ok().json(ApiResponse::success(webhooks)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "Webhooks",
+    summary = "Delete a webhook subscription",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "The deleted subscription", body = ApiResponse<WebhookSubscription>),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+    )
+)]
+pub async fn delete_webhook(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let webhook_id = path.into_inner();
+    
+    let subscription = storage.remove_webhook(&webhook_id).ok_or_else(|| ApiError::not_found("Webhook"))?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(subscription)))
 }
 
-pub async fn get_weather(path: web::Path<String>) -> Result<HttpResponse> {
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}/deliveries",
+    tag = "Webhooks",
+    summary = "List deliveries for a subscription",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Deliveries, newest first", body = ApiResponse<Vec<WebhookDelivery>>),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_webhook_deliveries(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let webhook_id = path.into_inner();
+    
+    if storage.get_webhook(&webhook_id).is_none() {
+        return Err(ApiError::not_found("Webhook"));
+    }
+    
+    let deliveries = storage.get_webhook_deliveries(&webhook_id);
+    Ok(HttpResponse::Ok().json(ApiResponse::success(deliveries)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks/deliveries/{id}/redeliver",
+    tag = "Webhooks",
+    summary = "Queue a delivery for another attempt",
+    params(("id" = String, Path, description = "Delivery id")),
+    responses(
+        (status = 200, description = "The re-queued delivery", body = ApiResponse<WebhookDelivery>),
+        (status = 404, description = "Delivery not found", body = ErrorResponse),
+    )
+)]
+pub async fn redeliver_webhook(
+    storage: web::Data<Arc<Storage>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let delivery_id = path.into_inner();
+    
+    let mut delivery = storage.get_webhook_delivery(&delivery_id).ok_or_else(|| ApiError::not_found("Delivery"))?;
+    
+    // Requeue with a fresh retry budget; the attempt log is kept
+    delivery.status = DeliveryStatus::Pending;
+    delivery.retries = 0;
+    delivery.next_attempt_at = Some(Utc::now());
+    
+    let delivery = storage.upsert_webhook_delivery(delivery);
+    Ok(HttpResponse::Ok().json(ApiResponse::success(delivery)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/weather/{location}",
+    tag = "Weather",
+    summary = "Current temperature at a location",
+    params(("location" = String, Path, description = "City name")),
+    responses(
+        (status = 200, description = "Current weather", body = ApiResponse<WeatherResponse>),
+        (status = 502, description = "Weather service unavailable", body = ErrorResponse),
+    )
+)]
+pub async fn get_weather(storage: web::Data<Arc<Storage>>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let location = path.into_inner();
     
-    match WeatherService::get_temperature(&location).await {
+    match WeatherService::get_temperature(&storage.weather_url, &location).await {
         Ok(temperature) => {
             let weather = WeatherResponse {
                 temperature,
@@ -186,11 +1754,22 @@ pub async fn get_weather(path: web::Path<String>) -> Result<HttpResponse> {
             };
             Ok(HttpResponse::Ok().json(ApiResponse::success(weather)))
         }
-        Err(e) => Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error(e.to_string()))),
+        Err(e) => Err(ApiError::Upstream(e.to_string())),
     }
 }
 
-pub async fn verify_password(req: web::Json<VerifyPasswordRequest>) -> Result<HttpResponse> {
+#[utoipa::path(
+    post,
+    path = "/auth/verify",
+    tag = "Auth",
+    summary = "Verify an action password",
+    request_body = VerifyPasswordRequest,
+    responses(
+        (status = 200, description = "Verification result", body = ApiResponse<VerifyPasswordResponse>),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+    )
+)]
+pub async fn verify_password(req: web::Json<VerifyPasswordRequest>) -> Result<HttpResponse, ApiError> {
     let is_valid = PasswordService::verify_password(&req.password, &req.action);
     
     let response = VerifyPasswordResponse {
@@ -203,4 +1782,750 @@ pub async fn verify_password(req: web::Json<VerifyPasswordRequest>) -> Result<Ht
     };
     
     Ok(HttpResponse::Ok().json(response))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, middleware::from_fn, test as actix_test, App};
+    use serde_json::{json, Value};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// The app as main serves it, minus logging and CORS.
+    macro_rules! test_app {
+        ($storage:expr) => {
+            actix_test::init_service(
+                App::new()
+                    .app_data(web::Data::new($storage.clone()))
+                    .wrap(from_fn(middleware::wire_format))
+                    .configure(crate::app_config),
+            )
+            .await
+        };
+    }
+
+    /// Sends a JSON body and returns the response.
+    macro_rules! send {
+        ($app:expr, $method:ident, $uri:expr, $body:expr) => {
+            actix_test::call_service(&$app, actix_test::TestRequest::$method().uri($uri).set_json($body).to_request()).await
+        };
+    }
+
+    async fn mock_storage() -> Arc<Storage> {
+        mock_storage_at(5.0).await
+    }
+    
+    /// Mock data with weather lookups answered by `fake_weather`.
+    async fn mock_storage_at(temperature: f64) -> Arc<Storage> {
+        let storage = Arc::new(Storage::new().with_weather_url(fake_weather(temperature).await));
+        storage.initialize_mock_data().await;
+        storage
+    }
+    
+    /// Serves OpenWeather's current weather shape with a fixed temperature,
+    /// returning its URL.
+    async fn fake_weather(temperature: f64) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/weather", listener.local_addr().unwrap());
+        let body = json!({
+            "name": "Pune",
+            "main": { "temp": temperature, "humidity": 50.0 },
+            "weather": [{ "description": "clear sky" }],
+        })
+        .to_string();
+        actix_web::rt::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let mut buffer = [0; 1024];
+                    match socket.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    fn event_body(event_type: &str) -> Value {
+        json!({ "location": "Pune", "temperature": 5.0, "event_type": event_type, "reporter": "logi-001" })
+    }
+
+    fn add_logistics_partner(storage: &Storage, id: &str) {
+        let mut user = storage.get_user("logi-001").expect("mock carrier");
+        user.id = id.to_string();
+        storage.users.insert(user.id.clone(), user);
+    }
+
+    fn add_leg(storage: &Storage, shipment_id: &str, partner: &str) {
+        storage
+            .modify_shipment(shipment_id, |shipment| {
+                shipment.legs.push(Leg::new(partner.to_string()));
+                Ok(())
+            })
+            .expect("leg added");
+    }
+
+    #[actix_web::test]
+    async fn first_event_starts_the_first_leg() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        
+        let res = send!(app, post, "/api/v2/shipments/ship-001/events", event_body("transit"));
+        assert_eq!(res.status(), StatusCode::OK);
+        
+        let shipment = storage.get_shipment("ship-001").unwrap();
+        assert!(shipment.legs[0].started_at.is_some());
+        
+        // The carrier has the goods now, so it can't be swapped out
+        let res = send!(app, put, "/api/v2/shipments/ship-001/logistics-partner", json!({ "logistics_partner": "logi-001" }));
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn imported_delivery_before_the_last_leg_is_refused() {
+        let storage = mock_storage().await;
+        add_leg(&storage, "ship-001", "logi-001");
+        let app = test_app!(storage);
+        
+        let mut shipment = storage.get_shipment("ship-001").unwrap();
+        for (id, event_type) in [("event-1", EventType::Pickup), ("event-2", EventType::Delivery)] {
+            shipment.events.push(ShipmentEvent {
+                id: id.to_string(),
+                timestamp: Utc::now(),
+                location: "Pune".to_string(),
+                temperature: 5.0,
+                verified_temperature: 5.0,
+                reporter: "logi-001".to_string(),
+                event_type,
+                is_temperature_valid: true,
+                humidity: None,
+                shock: None,
+                light: None,
+                violations: vec![],
+                coordinates: None,
+                hash: String::new(),
+            });
+        }
+        let document = serde_json::to_value(epcis::export(&shipment)).unwrap();
+        
+        let res = send!(app, post, "/api/v2/shipments/ship-001/epcis", document);
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert!(body["message"].as_str().unwrap_or_default().contains("last leg"), "{body}");
+        
+        // Nothing from the rejected document is kept
+        let shipment = storage.get_shipment("ship-001").unwrap();
+        assert!(shipment.events.is_empty());
+        assert!(matches!(shipment.status, ShipmentStatus::Pending));
+    }
+
+    #[actix_web::test]
+    async fn returned_goods_stay_compromised_on_verification() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", event_body("pickup")).status(), StatusCode::OK);
+        let readings = json!({ "readings": [{ "timestamp": Utc::now(), "temperature": 5.0, "shock": 20.0 }] });
+        let res = send!(app, post, "/api/v2/shipments/ship-001/telemetry", readings);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["status"], "compromised");
+        
+        let request = json!({ "reason": "damaged", "requested_by": "cons-001" });
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/return", request).status(), StatusCode::OK);
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", event_body("delivery")).status(), StatusCode::OK);
+        assert!(matches!(storage.get_shipment("ship-001").unwrap().status, ShipmentStatus::Returned));
+        
+        let token = VerificationService::issue_token(&storage.verification_key, "ship-001");
+        let req = actix_test::TestRequest::get().uri(&format!("/api/verify/{}", token)).to_request();
+        let body: Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"]["cold_chain_intact"], false, "{body}");
+    }
+
+    #[actix_web::test]
+    async fn telemetry_neither_reaches_closed_shipments_nor_compromises_delivered_ones() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        let shock = || json!({ "readings": [{ "timestamp": Utc::now(), "shock": 20.0 }] });
+        
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", event_body("pickup")).status(), StatusCode::OK);
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", event_body("delivery")).status(), StatusCode::OK);
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/telemetry", shock()).status(), StatusCode::OK);
+        let shipment = storage.get_shipment("ship-001").unwrap();
+        assert!(matches!(shipment.status, ShipmentStatus::Delivered));
+        assert!(shipment.compromised_at.is_none());
+        
+        let request = json!({ "reason": "damaged", "requested_by": "cons-001" });
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/return", request).status(), StatusCode::OK);
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", event_body("delivery")).status(), StatusCode::OK);
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/telemetry", shock()).status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn recalls_freeze_escrow_until_closed() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", event_body("pickup")).status(), StatusCode::OK);
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", event_body("delivery")).status(), StatusCode::OK);
+        
+        let request = json!({ "product_id": "prod-001", "lot": "LOT-2024-001", "reason": "Seal defect", "issued_by": "manu-001" });
+        let res = send!(app, post, "/api/v2/recalls", request);
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = actix_test::read_body_json(res).await;
+        let recall_id = body["data"]["recall"]["id"].as_str().unwrap().to_string();
+        assert_eq!(body["data"]["shipments"][0]["shipment_id"], "ship-001");
+        assert_eq!(body["data"]["shipments"][0]["escrow_frozen"], true);
+        assert_eq!(storage.get_shipment("ship-001").unwrap().recalls, vec![recall_id.clone()]);
+        
+        let confirm = || actix_test::TestRequest::post().uri("/api/v2/shipments/ship-001/confirm").to_request();
+        assert_eq!(actix_test::call_service(&app, confirm()).await.status(), StatusCode::CONFLICT);
+        
+        let close = || actix_test::TestRequest::post().uri(&format!("/api/v2/recalls/{}/close", recall_id)).to_request();
+        let res = actix_test::call_service(&app, close()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["recall"]["status"], "closed");
+        assert_eq!(body["data"]["shipments"][0]["escrow_frozen"], false);
+        assert_eq!(actix_test::call_service(&app, close()).await.status(), StatusCode::CONFLICT);
+        
+        assert_eq!(actix_test::call_service(&app, confirm()).await.status(), StatusCode::OK);
+        assert!(storage.get_shipment("ship-001").unwrap().escrow_released);
+    }
+
+    #[actix_web::test]
+    async fn recalls_only_take_the_manufacturers_word() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        
+        let request = json!({ "product_id": "prod-001", "reason": "Seal defect", "issued_by": "cons-001" });
+        assert_eq!(send!(app, post, "/api/v2/recalls", request).status(), StatusCode::BAD_REQUEST);
+        assert!(storage.list_recalls().is_empty());
+        assert!(storage.get_shipment("ship-001").unwrap().recalls.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn if_match_is_compared_strongly_against_every_listed_etag() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        let version = storage.get_shipment("ship-001").unwrap().version;
+        let assign = |if_match: Option<String>| {
+            let req = actix_test::TestRequest::put()
+                .uri("/api/v2/shipments/ship-001/logistics-partner")
+                .set_json(json!({ "logistics_partner": "logi-001" }));
+            match if_match {
+                Some(if_match) => req.insert_header((header::IF_MATCH, if_match)).to_request(),
+                None => req.to_request(),
+            }
+        };
+        
+        let stale = format!("\"{}\"", version + 1);
+        assert_eq!(actix_test::call_service(&app, assign(Some(stale))).await.status(), StatusCode::PRECONDITION_FAILED);
+        let weak = format!("W/\"{}\"", version);
+        assert_eq!(actix_test::call_service(&app, assign(Some(weak))).await.status(), StatusCode::PRECONDITION_FAILED);
+        let malformed = "version-1".to_string();
+        assert_eq!(actix_test::call_service(&app, assign(Some(malformed))).await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(storage.get_shipment("ship-001").unwrap().version, version, "failed preconditions change nothing");
+        
+        let listed = format!("\"{}\", \"{}\"", version + 7, version);
+        let res = actix_test::call_service(&app, assign(Some(listed))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ETAG).unwrap().to_str().unwrap(), format!("\"{}\"", version + 1));
+        
+        // Without If-Match, or with `*`, any version will do
+        assert_eq!(actix_test::call_service(&app, assign(None)).await.status(), StatusCode::OK);
+        assert_eq!(actix_test::call_service(&app, assign(Some("*".to_string()))).await.status(), StatusCode::OK);
+        assert_eq!(storage.get_shipment("ship-001").unwrap().version, version + 3);
+    }
+
+    #[actix_web::test]
+    async fn device_keys_only_report_for_their_owners_shipments() {
+        let storage = mock_storage().await;
+        add_logistics_partner(&storage, "logi-002");
+        let app = test_app!(storage);
+        
+        let res = send!(app, post, "/api/v2/devices", json!({ "name": "Reefer logger", "owner": "logi-001" }));
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = actix_test::read_body_json(res).await;
+        let device_id = body["data"]["device"]["id"].as_str().unwrap().to_string();
+        let api_key = body["data"]["api_key"].as_str().unwrap().to_string();
+        let report = |key: &str| {
+            actix_test::TestRequest::post()
+                .uri("/api/v2/shipments/ship-001/events")
+                .insert_header((DEVICE_KEY_HEADER, key))
+                .set_json(event_body("pickup"))
+                .to_request()
+        };
+        
+        let reassign = |partner: &str| json!({ "logistics_partner": partner });
+        assert_eq!(send!(app, put, "/api/v2/shipments/ship-001/logistics-partner", reassign("logi-002")).status(), StatusCode::OK);
+        assert_eq!(actix_test::call_service(&app, report(&api_key)).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(send!(app, put, "/api/v2/shipments/ship-001/logistics-partner", reassign("logi-001")).status(), StatusCode::OK);
+        
+        // The device is the reporter, whatever the body claims
+        assert_eq!(actix_test::call_service(&app, report(&api_key)).await.status(), StatusCode::OK);
+        assert_eq!(storage.get_shipment("ship-001").unwrap().events[0].reporter, device_id);
+        assert!(storage.get_device(&device_id).unwrap().last_seen_at.is_some());
+        
+        assert_eq!(actix_test::call_service(&app, report("not-a-key")).await.status(), StatusCode::UNAUTHORIZED);
+        let revoke = actix_test::TestRequest::post().uri(&format!("/api/v2/devices/{}/revoke", device_id)).to_request();
+        assert_eq!(actix_test::call_service(&app, revoke).await.status(), StatusCode::OK);
+        assert_eq!(actix_test::call_service(&app, report(&api_key)).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn telemetry_batches_spend_the_excursion_budget() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        // Telemetry is stored to the millisecond
+        let start = DateTime::from_timestamp_millis(Utc::now().timestamp_millis()).unwrap() - chrono::Duration::hours(2);
+        let at = |minutes: i64| start + chrono::Duration::minutes(minutes);
+        let batch = |readings: &[(i64, f64)]| {
+            let readings: Vec<Value> = readings
+                .iter()
+                .map(|(minutes, temperature)| json!({ "timestamp": at(*minutes), "temperature": temperature }))
+                .collect();
+            json!({ "readings": readings })
+        };
+        storage
+            .modify_shipment("ship-001", |shipment| {
+                shipment.status = ShipmentStatus::InTransit;
+                shipment.legs[0].started_at = Some(start);
+                Ok(())
+            })
+            .unwrap();
+        
+        // Ten minutes out of range is within the mock product's 30 minute budget
+        let res = send!(app, post, "/api/v2/shipments/ship-001/telemetry", batch(&[(0, 5.0), (10, 12.0), (20, 5.0)]));
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["accepted"], 3);
+        assert_eq!(body["data"]["excursions"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"]["status"], "in_transit");
+        
+        let res = send!(app, post, "/api/v2/shipments/ship-001/telemetry", batch(&[(30, 12.0), (60, 5.0)]));
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["status"], "compromised");
+        let shipment = storage.get_shipment("ship-001").unwrap();
+        assert_eq!(shipment.compromised_at, Some(at(50)), "the budget ran out twenty minutes into the second excursion");
+        
+        let from = at(15).to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let req = actix_test::TestRequest::get().uri(&format!("/api/v2/shipments/ship-001/telemetry?from={}", from)).to_request();
+        let body: Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), 3);
+        
+        let empty = json!({ "readings": [] });
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/telemetry", empty).status(), StatusCode::BAD_REQUEST);
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-404/telemetry", batch(&[(0, 5.0)])).status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn environmental_constraints_are_registered_and_enforced() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        let product = |constraints: Value| json!({
+            "name": "Vaccine", "description": "", "manufacturer": "manu-001", "logistics_partner": "logi-001",
+            "min_temperature": 2.0, "max_temperature": 8.0, "constraints": constraints,
+        });
+        
+        let res = send!(app, post, "/api/v2/products", product(json!([{ "metric": "light", "max": 100.0 }])));
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["constraints"][0]["metric"], "light");
+        let inverted = product(json!([{ "metric": "humidity", "min": 70.0, "max": 30.0 }]));
+        assert_eq!(send!(app, post, "/api/v2/products", inverted).status(), StatusCode::BAD_REQUEST);
+        
+        // The mock product keeps humidity within 30-65 %RH
+        let mut event = event_body("pickup");
+        event["humidity"] = json!(80.0);
+        let body: Value = actix_test::read_body_json(send!(app, post, "/api/v2/shipments/ship-001/events", event)).await;
+        assert_eq!(body["data"]["status"], "compromised");
+        let recorded = &body["data"]["events"][0];
+        assert_eq!(recorded["is_temperature_valid"], true);
+        assert_eq!(recorded["violations"][0]["metric"], "humidity");
+    }
+
+    #[actix_web::test]
+    async fn streams_carry_only_the_subscribed_shipments_changes() {
+        let storage = mock_storage().await;
+        let mut other = storage.get_shipment("ship-001").unwrap();
+        other.id = "ship-002".to_string();
+        storage.shipments.insert(other.id.clone(), other);
+        let app = test_app!(storage);
+        
+        let req = actix_test::TestRequest::get().uri("/api/v2/shipments/stream?shipment_id=ship-001").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "text/event-stream");
+        let mut stream = res.into_body();
+        
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-002/events", event_body("pickup")).status(), StatusCode::OK);
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", event_body("pickup")).status(), StatusCode::OK);
+        
+        let mut frames = Vec::new();
+        while frames.len() < 2 {
+            let chunk = std::future::poll_fn(|cx| actix_web::body::MessageBody::poll_next(std::pin::Pin::new(&mut stream), cx))
+                .await
+                .expect("stream stays open")
+                .unwrap();
+            let frame = String::from_utf8(chunk.to_vec()).unwrap();
+            // Keep-alive comments are interleaved with the updates
+            if !frame.starts_with(':') {
+                frames.push(frame);
+            }
+        }
+        assert!(frames[0].starts_with("event: event_added\n"), "{}", frames[0]);
+        assert!(frames[1].starts_with("event: status_changed\n"), "{}", frames[1]);
+        assert!(frames.iter().all(|frame| frame.contains("\"ship-001\"") && !frame.contains("ship-002")));
+        
+        let req = actix_test::TestRequest::get().uri("/api/v2/shipments/stream?user_id=cons-001").to_request();
+        assert_eq!(actix_test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn products_are_updated_and_soft_deleted() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        let get = |uri: &str| actix_test::TestRequest::get().uri(uri).to_request();
+        let original_name = storage.get_product("prod-001").unwrap().name;
+        
+        let res = send!(app, patch, "/api/v2/products/prod-001", json!({ "name": "Renamed vaccine" }));
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["name"], "Renamed vaccine");
+        assert!(body["data"]["updated_at"].is_string());
+        assert_eq!(storage.get_shipment("ship-001").unwrap().product.name, original_name, "shipments keep their snapshot");
+        
+        let delete = actix_test::TestRequest::delete().uri("/api/v2/products/prod-001").to_request();
+        assert_eq!(actix_test::call_service(&app, delete).await.status(), StatusCode::OK);
+        let listed = |body: &Value| body["data"]["items"].as_array().unwrap().iter().any(|product| product["id"] == "prod-001");
+        assert!(!listed(&actix_test::call_and_read_body_json(&app, get("/api/v2/products")).await));
+        assert!(listed(&actix_test::call_and_read_body_json(&app, get("/api/v2/products?include_deleted=true")).await));
+        let body: Value = actix_test::call_and_read_body_json(&app, get("/api/v2/products/prod-001")).await;
+        assert!(body["data"]["deleted_at"].is_string());
+        assert_eq!(send!(app, patch, "/api/v2/products/prod-001", json!({ "name": "Again" })).status(), StatusCode::CONFLICT);
+        
+        assert_eq!(actix_test::call_service(&app, get("/api/v2/products/prod-404")).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(actix_test::call_service(&app, get("/api/v2/users/cons-001")).await.status(), StatusCode::OK);
+        assert_eq!(actix_test::call_service(&app, get("/api/v2/users/cons-404")).await.status(), StatusCode::NOT_FOUND);
+        let res = actix_test::call_service(&app, get("/api/v2/shipments/ship-001")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().contains_key(header::ETAG));
+    }
+
+    #[actix_web::test]
+    async fn errors_share_one_envelope_with_stable_codes() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        let error = |res: actix_web::dev::ServiceResponse| async move {
+            let status = res.status();
+            let body: Value = actix_test::read_body_json(res).await;
+            assert_eq!(body["success"], false);
+            assert!(body["data"].is_null());
+            assert!(!body["message"].as_str().unwrap().is_empty());
+            (status, body["code"].as_str().unwrap().to_string())
+        };
+        
+        let req = actix_test::TestRequest::get().uri("/api/v2/shipments/ship-404").to_request();
+        assert_eq!(error(actix_test::call_service(&app, req).await).await, (StatusCode::NOT_FOUND, "not_found".to_string()));
+        
+        // Extractor failures are reported like handler errors
+        let req = actix_test::TestRequest::post()
+            .uri("/api/v2/shipments/ship-001/events")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload("{ not json")
+            .to_request();
+        assert_eq!(error(actix_test::call_service(&app, req).await).await, (StatusCode::BAD_REQUEST, "validation_failed".to_string()));
+        let req = actix_test::TestRequest::get().uri("/api/v2/shipments?limit=many").to_request();
+        assert_eq!(error(actix_test::call_service(&app, req).await).await, (StatusCode::BAD_REQUEST, "validation_failed".to_string()));
+        
+        let res = send!(app, post, "/api/v2/shipments/ship-001/handoffs", json!({ "leg": 0, "user_id": "logi-001", "location": "Pune" }));
+        assert_eq!(error(res).await, (StatusCode::CONFLICT, "conflict".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn every_failed_rule_is_reported_at_once() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        
+        let request = json!({
+            "consumer": "manu-001",
+            "escrow": { "amount_minor": 0, "currency": "inr" },
+            "line_items": [{ "product_id": "prod-001", "quantity": 1.0 }, { "product_id": "", "quantity": -2.0 }],
+        });
+        let res = send!(app, post, "/api/v2/shipments", request);
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["code"], "validation_failed");
+        let fields: Vec<&str> = body["errors"].as_array().unwrap().iter().map(|error| error["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, [
+            "consumer",
+            "escrow.amount_minor",
+            "escrow.currency",
+            "line_items[1].product_id",
+            "line_items[1].quantity",
+        ]);
+        assert!(storage.get_shipments_with_product("prod-001").len() == 1, "nothing was funded");
+        
+        // Rules spanning several fields have no single field to blame
+        let inverted = json!({
+            "name": "Vaccine", "description": "", "manufacturer": "manu-001", "logistics_partner": "logi-001",
+            "min_temperature": 8.0, "max_temperature": 2.0,
+        });
+        let body: Value = actix_test::read_body_json(send!(app, post, "/api/v2/products", inverted)).await;
+        assert_eq!(body["errors"][0]["code"], "invalid_range");
+        assert!(body["errors"][0]["field"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn v1_keeps_its_shapes_and_is_marked_deprecated() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        
+        let req = actix_test::TestRequest::get().uri("/api/v2/shipments/ship-001").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert!(res.headers().get("Deprecation").is_none());
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["escrow"], json!({ "amount_minor": 5_000_000, "currency": "INR" }));
+        assert!(body["data"].get("escrow_amount").is_none());
+        
+        for uri in ["/api/v1/shipments/ship-001", "/api/shipments/ship-001"] {
+            let res = actix_test::call_service(&app, actix_test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers().get("Deprecation").unwrap(), "true");
+            assert_eq!(res.headers().get("Link").unwrap(), "</api/v2>; rel=\"successor-version\"");
+            let body: Value = actix_test::read_body_json(res).await;
+            assert_eq!(body["data"]["escrow_amount"], 50_000.0);
+            assert!(body["data"].get("escrow").is_none());
+            let product = &body["data"]["product"];
+            assert!(product["unit_price"].is_number());
+            assert!(product.get("gtin").is_none() && product.get("constraints").is_none());
+        }
+        
+        // v1 events carry no coordinates
+        let mut event = event_body("transit");
+        event["coordinates"] = json!({ "latitude": 18.52, "longitude": 73.86 });
+        let body: Value = actix_test::read_body_json(send!(app, post, "/api/v1/shipments/ship-001/events", event)).await;
+        let events = body["data"]["events"].as_array().unwrap();
+        assert!(events.iter().all(|event| event.get("coordinates").is_none()));
+        assert!(storage.get_shipment("ship-001").unwrap().events.iter().any(|event| event.coordinates.is_some()));
+        
+        // A v1 escrow in rupees becomes a single item priced at it
+        let request = json!({ "product_id": "prod-001", "consumer": "cons-001", "escrow_amount": 750.5 });
+        let res = send!(app, post, "/api/v1/shipments", request);
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["escrow_amount"], 750.5);
+        let shipment = storage.get_shipment(body["data"]["id"].as_str().unwrap()).unwrap();
+        assert_eq!(shipment.escrow, Money { amount_minor: 75_050, currency: "INR".to_string() });
+        assert_eq!(shipment.line_items.len(), 1);
+        assert_eq!(shipment.line_items[0].unit_price, Some(shipment.escrow.clone()));
+        
+        // The v1 payload is validated in its own terms
+        let request = json!({ "product_id": "prod-001", "consumer": "cons-001", "escrow_amount": 0.0 });
+        let res = send!(app, post, "/api/v1/shipments", request);
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["errors"][0]["field"], "escrow_amount");
+    }
+
+    #[actix_web::test]
+    async fn camel_case_is_negotiated_both_ways() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        
+        // Plain JSON stays snake_case but still varies on Accept for caches
+        let res = actix_test::call_service(&app, actix_test::TestRequest::get().uri("/api/v2/shipments/ship-001").to_request()).await;
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept");
+        let body: Value = actix_test::read_body_json(res).await;
+        assert!(body["data"]["created_at"].is_string());
+        
+        let req = actix_test::TestRequest::get()
+            .uri("/api/v2/shipments/ship-001")
+            .insert_header((header::ACCEPT, wire::CAMEL_JSON))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), wire::CAMEL_JSON);
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept");
+        let body: Value = actix_test::read_body_json(res).await;
+        let shipment = &body["data"];
+        assert_eq!(shipment["escrow"]["amountMinor"], 5_000_000);
+        assert_eq!(shipment["lineItems"][0]["productId"], "prod-001");
+        let created_at = storage.get_shipment("ship-001").unwrap().created_at;
+        assert_eq!(shipment["createdAt"], created_at.timestamp_millis());
+        assert!(shipment.get("created_at").is_none());
+        
+        // camelCase bodies are read, and errors name fields the way the client sent them
+        let event = json!({ "location": "Pune", "temperature": 5.0, "eventType": "transit", "reporter": "logi-001" });
+        let req = actix_test::TestRequest::post()
+            .uri("/api/v2/shipments/ship-001/events")
+            .insert_header((header::CONTENT_TYPE, wire::CAMEL_JSON))
+            .insert_header((header::ACCEPT, wire::CAMEL_JSON))
+            .set_payload(event.to_string())
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["events"][0]["eventType"], "transit");
+        assert!(body["data"]["events"][0]["timestamp"].is_i64());
+        
+        let req = actix_test::TestRequest::post()
+            .uri("/api/v2/products")
+            .insert_header((header::CONTENT_TYPE, wire::CAMEL_JSON))
+            .insert_header((header::ACCEPT, wire::CAMEL_JSON))
+            .set_payload(json!({
+                "name": "Vaccine", "description": "", "manufacturer": "manu-001", "logisticsPartner": "logi-001",
+                "minTemperature": 2.0, "maxTemperature": 8.0, "excursionBudgetMinutes": -1.0,
+            }).to_string())
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["errors"][0]["field"], "excursionBudgetMinutes");
+        
+        // Streams negotiate on their own, frame by frame
+        let req = actix_test::TestRequest::get()
+            .uri("/api/v2/shipments/stream?shipment_id=ship-001")
+            .insert_header((header::ACCEPT, wire::CAMEL_JSON))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept");
+        let mut stream = res.into_body();
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", event_body("transit")).status(), StatusCode::OK);
+        let frame = loop {
+            let chunk = std::future::poll_fn(|cx| actix_web::body::MessageBody::poll_next(std::pin::Pin::new(&mut stream), cx))
+                .await
+                .expect("stream stays open")
+                .unwrap();
+            let frame = String::from_utf8(chunk.to_vec()).unwrap();
+            if !frame.starts_with(':') {
+                break frame;
+            }
+        };
+        let data: Value = serde_json::from_str(frame.lines().find_map(|line| line.strip_prefix("data: ")).unwrap()).unwrap();
+        assert_eq!(data["shipmentId"], "ship-001");
+        assert!(data["timestamp"].is_i64());
+        assert!(data["event"]["eventType"].is_string() && data.get("shipment_id").is_none());
+        
+        // Binary responses pass through untouched
+        let req = actix_test::TestRequest::get()
+            .uri("/api/v2/shipments/ship-001/qr")
+            .insert_header((header::ACCEPT, wire::CAMEL_JSON))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(header::VARY).is_none());
+        assert_ne!(res.headers().get(header::CONTENT_TYPE).unwrap(), wire::CAMEL_JSON);
+    }
+
+    #[actix_web::test]
+    async fn legs_are_reassigned_only_until_they_start() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        add_logistics_partner(&storage, "logi-002");
+        add_logistics_partner(&storage, "logi-003");
+        add_leg(&storage, "ship-001", "logi-003");
+        let uri = "/api/v2/shipments/ship-001/logistics-partner";
+        
+        let res = send!(app, put, uri, json!({ "logistics_partner": "logi-002" }));
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["logistics_partner"], "logi-002");
+        assert_eq!(body["data"]["legs"][0]["partner"], "logi-002");
+        assert_eq!(body["data"]["legs"][1]["partner"], "logi-003");
+        
+        let res = send!(app, put, uri, json!({ "logistics_partner": "manu-001" }));
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = send!(app, put, uri, json!({ "logistics_partner": "logi-001", "leg": 2 }));
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        
+        // Pickup starts the first leg; the next stays open until its handoff begins
+        let mut pickup = event_body("transit");
+        pickup["reporter"] = json!("logi-002");
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/events", pickup).status(), StatusCode::OK);
+        let res = send!(app, put, uri, json!({ "logistics_partner": "logi-001" }));
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        
+        let res = send!(app, put, uri, json!({ "logistics_partner": "logi-001", "leg": 1 }));
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["logistics_partner"], "logi-002", "the current leg's partner is unchanged");
+        
+        let handoff = json!({ "leg": 0, "user_id": "logi-002", "location": "Nashik" });
+        assert_eq!(send!(app, post, "/api/v2/shipments/ship-001/handoffs", handoff).status(), StatusCode::OK);
+        let res = send!(app, put, uri, json!({ "logistics_partner": "logi-003", "leg": 1 }));
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(storage.get_shipment("ship-001").unwrap().legs[1].partner, "logi-001");
+    }
+
+    #[actix_web::test]
+    async fn partners_never_hand_off_to_themselves() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        add_logistics_partner(&storage, "logi-002");
+        add_leg(&storage, "ship-001", "logi-002");
+        
+        let request = json!({
+            "consumer": "cons-001",
+            "line_items": [{ "product_id": "prod-001", "quantity": 1.0 }],
+            "legs": ["logi-001", "logi-002", "logi-002"],
+        });
+        let res = send!(app, post, "/api/v2/shipments", request);
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["errors"][0]["field"], "legs");
+        assert_eq!(body["errors"][0]["code"], "repeated_partner");
+        
+        let uri = "/api/v2/shipments/ship-001/logistics-partner";
+        assert_eq!(send!(app, put, uri, json!({ "logistics_partner": "logi-002" })).status(), StatusCode::BAD_REQUEST);
+        assert_eq!(send!(app, put, uri, json!({ "logistics_partner": "logi-001", "leg": 1 })).status(), StatusCode::BAD_REQUEST);
+        let shipment = storage.get_shipment("ship-001").unwrap();
+        assert_eq!(shipment.legs[0].partner, "logi-001");
+        assert_eq!(shipment.legs[1].partner, "logi-002");
+    }
+
+    #[actix_web::test]
+    async fn reported_temperatures_are_checked_against_the_weather() {
+        let storage = mock_storage_at(31.0).await;
+        let app = test_app!(storage);
+        
+        let res = send!(app, post, "/api/v2/shipments/ship-001/events", event_body("transit"));
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = actix_test::read_body_json(res).await;
+        let event = &body["data"]["events"][0];
+        assert_eq!(event["temperature"], 5.0);
+        assert_eq!(event["verified_temperature"], 31.0);
+        assert_eq!(event["is_temperature_valid"], false);
+        assert_eq!(body["data"]["status"], "compromised");
+        
+        let res = actix_test::call_service(&app, actix_test::TestRequest::get().uri("/api/v2/weather/Pune").to_request()).await;
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["temperature"], 31.0);
+    }
+
+    #[actix_web::test]
+    async fn excursions_before_pickup_leave_the_shipment_cancellable() {
+        let storage = mock_storage().await;
+        let app = test_app!(storage);
+        
+        let readings = json!({ "readings": [{ "timestamp": Utc::now(), "temperature": 5.0, "shock": 9.0 }] });
+        let res = send!(app, post, "/api/v2/shipments/ship-001/telemetry", readings);
+        let body: Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["data"]["excursions"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"]["status"], "pending");
+        assert!(storage.get_shipment("ship-001").unwrap().compromised_at.is_none());
+        
+        let cancel = json!({ "reason": "Logger tripped in the warehouse", "requested_by": "cons-001" });
+        let res = send!(app, post, "/api/v2/shipments/ship-001/cancel", cancel);
+        assert_eq!(res.status(), StatusCode::OK);
+        let shipment = storage.get_shipment("ship-001").unwrap();
+        assert!(matches!(shipment.status, ShipmentStatus::Cancelled));
+        assert_eq!(shipment.escrow_refund, Some(shipment.escrow.clone()));
+    }
+}
@@ -1,26 +1,78 @@
-use actix_web::{web, App, HttpServer, Result, HttpResponse, middleware::Logger};
+use actix_web::{web, App, HttpServer, Result, HttpResponse, middleware::{from_fn, Logger}};
 use actix_cors::Cors;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::SigningKey;
 use std::sync::Arc;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 
+mod cli;
+mod epcis;
+mod errors;
+mod gs1;
 mod models;
 mod services;
 mod handlers;
+mod middleware;
+mod openapi;
 mod storage;
+#[cfg(test)]
+mod typescript;
+mod versions;
+mod wire;
 
+use openapi::ApiDoc;
+use services::WebhookService;
+use errors::ApiError;
 use storage::Storage;
+use versions::{ApiVersion, V1, V2};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
     
-    // Initialize storage
-    let storage = Arc::new(Storage::new());
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("verify-cert") {
+        std::process::exit(cli::verify_cert(&args[2..]));
+    }
     
-    // Initialize with mock data
+        // Initialize storage
+    let webhook_store = std::env::var("VERICHAIN_WEBHOOK_STORE").unwrap_or_else(|_| "webhooks.json".to_string());
+    let mut storage = Storage::new().with_webhook_store(webhook_store);
+    match std::env::var("VERICHAIN_VERIFICATION_KEY") {
+        Ok(key) => storage = storage.with_verification_key(key),
+        Err(_) => log::warn!("VERICHAIN_VERIFICATION_KEY is not set, verification QR codes will not survive a restart"),
+    }
+    match std::env::var("VERICHAIN_PUBLIC_URL") {
+        Ok(url) => storage = storage.with_public_url(url),
+        Err(_) => log::warn!("VERICHAIN_PUBLIC_URL is not set, verification QR codes will link to http://localhost:8080"),
+    }
+    if let Ok(url) = std::env::var("VERICHAIN_WEATHER_URL") {
+        storage = storage.with_weather_url(url);
+    }
+    match std::env::var("VERICHAIN_SIGNING_KEY") {
+        Ok(seed) => {
+            let seed = URL_SAFE_NO_PAD
+                .decode(seed.trim())
+                .ok()
+                .and_then(|seed| <[u8; 32]>::try_from(seed).ok())
+                .expect("VERICHAIN_SIGNING_KEY must be a base64url-encoded 32-byte Ed25519 seed");
+            storage = storage.with_signing_key(SigningKey::from_bytes(&seed));
+        }
+        Err(_) => log::warn!("VERICHAIN_SIGNING_KEY is not set, certificates will not verify against keys published before a restart"),
+    }
+    let storage = Arc::new(storage);
+    
+        // Initialize with mock data
     storage.initialize_mock_data().await;
     
+        // Deliver outbound webhooks in the background
+    actix_web::rt::spawn(WebhookService::run(storage.clone()));
+    actix_web::rt::spawn(Storage::run_webhook_store_writer(storage.clone()));
+    
     println!("🚀 Verichain Rust Backend starting on http://localhost:8080");
     
+    let shutdown_storage = storage.clone();
     HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
@@ -30,32 +82,103 @@ async fn main() -> std::io::Result<()> {
             
         App::new()
             .app_data(web::Data::new(storage.clone()))
+            .wrap(from_fn(middleware::wire_format))
             .wrap(cors)
             .wrap(Logger::default())
-            .service(
-                web::scope("/api")
-                    // Product routes
-                    .route("/products", web::get().to(handlers::get_products))
-                    .route("/products", web::post().to(handlers::register_product))
-                    
-                    // Shipment routes
-                    .route("/shipments", web::get().to(handlers::get_shipments))
-                    .route("/shipments", web::post().to(handlers::fund_escrow))
-                    .route("/shipments/{id}/events", web::post().to(handlers::add_event))
-                    .route("/shipments/{id}/confirm", web::post().to(handlers::confirm_delivery))
-                    
-                    // User routes
-                    .route("/users", web::get().to(handlers::get_users))
-                    .route("/users/{id}/shipments", web::get().to(handlers::get_user_shipments))
-                    
-                    // Weather route
-                    .route("/weather/{location}", web::get().to(handlers::get_weather))
-                    
-                    // Authentication routes
-                    .route("/auth/verify", web::post().to(handlers::verify_password))
-            )
+            .configure(app_config)
     })
     .bind("127.0.0.1:8080")?
     .run()
-    .await
-}
\ No newline at end of file
+    .await?;
+    
+    // Save whatever the writer hadn't got to yet
+    shutdown_storage.write_webhook_store();
+    Ok(())
+}
+
+/// Everything inside the outer middleware, shared with the handler tests.
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg
+        // Malformed bodies, query strings and paths get the same error shape as handler errors
+        .app_data(web::JsonConfig::default().error_handler(|err, _| ApiError::Validation(err.to_string()).into()))
+        .app_data(web::QueryConfig::default().error_handler(|err, _| ApiError::Validation(err.to_string()).into()))
+        .app_data(web::PathConfig::default().error_handler(|err, _| ApiError::Validation(err.to_string()).into()))
+        .service(SwaggerUi::new("/api/docs/{_:.*}").url("/api/openapi.json", ApiDoc::openapi()))
+        // Printed QR codes link here, so it stays put as API versions come and go
+        .route("/api/verify/{token}", web::get().to(handlers::verify_shipment))
+        .route("/api/certificates/key", web::get().to(handlers::get_certificate_key))
+        .service(web::scope("/api/v2").configure(api_routes::<V2>))
+        .service(web::scope("/api/v1").wrap(middleware::deprecated("/api/v2")).configure(api_routes::<V1>))
+        // Unversioned paths predate versioning and keep serving v1
+        .service(web::scope("/api").wrap(middleware::deprecated("/api/v2")).configure(api_routes::<V1>));
+}
+
+/// Routes mounted under each API version scope, shared with the OpenAPI route tests.
+fn api_routes<V: ApiVersion>(cfg: &mut web::ServiceConfig) {
+    cfg
+        // Product routes
+        .route("/products", web::get().to(handlers::get_products))
+        .route("/products", web::post().to(handlers::register_product).wrap(from_fn(middleware::idempotency)))
+        .route("/products/{id}", web::get().to(handlers::get_product))
+        .route("/products/{id}", web::patch().to(handlers::update_product))
+        .route("/products/{id}", web::delete().to(handlers::delete_product))
+        .route("/products/{id}/lots", web::get().to(handlers::get_product_lots))
+        .route("/products/{id}/lots/{lot}", web::get().to(handlers::trace_product_lot))
+    
+        // Shipment routes
+        .route("/shipments", web::get().to(handlers::get_shipments::<V>))
+        .route("/shipments", web::post().to(handlers::fund_escrow::<V>).wrap(from_fn(middleware::idempotency)))
+        .route("/shipments/stream", web::get().to(handlers::stream_shipment_updates::<V>))
+        .route("/shipments/{id}", web::get().to(handlers::get_shipment::<V>))
+        .route("/shipments/{id}/events", web::post().to(handlers::add_event::<V>).wrap(from_fn(middleware::idempotency)))
+        .route("/shipments/{id}/logistics-partner", web::put().to(handlers::assign_logistics_partner::<V>))
+        .route("/shipments/{id}/handoffs", web::post().to(handlers::acknowledge_handoff::<V>))
+        .route("/shipments/{id}/cancel", web::post().to(handlers::cancel_shipment::<V>).wrap(from_fn(middleware::idempotency)))
+        .route("/shipments/{id}/return", web::post().to(handlers::return_shipment::<V>).wrap(from_fn(middleware::idempotency)))
+        .route("/shipments/{id}/confirm", web::post().to(handlers::confirm_delivery::<V>).wrap(from_fn(middleware::idempotency)))
+        .route("/shipments/{id}/telemetry", web::post().to(handlers::ingest_telemetry))
+        .route("/shipments/{id}/telemetry", web::get().to(handlers::get_telemetry))
+        .route("/shipments/{id}/thermal-summary", web::get().to(handlers::get_thermal_summary))
+        .route("/shipments/{id}/qr", web::get().to(handlers::get_shipment_qr))
+        .route("/shipments/{id}/certificate", web::get().to(handlers::get_shipment_certificate))
+        .route("/shipments/{id}/gs1", web::get().to(handlers::get_shipment_gs1))
+        .route("/shipments/{id}/epcis", web::get().to(handlers::export_epcis))
+        .route("/shipments/{id}/epcis", web::post().to(handlers::import_epcis::<V>))
+    
+        // Recall routes
+        .route("/recalls", web::get().to(handlers::get_recalls))
+        .route("/recalls", web::post().to(handlers::create_recall).wrap(from_fn(middleware::idempotency)))
+        .route("/recalls/{id}", web::get().to(handlers::get_recall))
+        .route("/recalls/{id}/close", web::post().to(handlers::close_recall))
+    
+        // User routes
+        .route("/users", web::get().to(handlers::get_users))
+        .route("/users/{id}", web::get().to(handlers::get_user))
+        .route("/users/{id}/shipments", web::get().to(handlers::get_user_shipments::<V>))
+        .route("/users/{id}/devices", web::get().to(handlers::get_user_devices))
+        .route("/users/{id}/webhooks", web::get().to(handlers::get_user_webhooks))
+        .route("/users/{id}/webhooks", web::post().to(handlers::create_webhook))
+    
+        // Webhook routes
+        .route("/webhooks/{id}", web::delete().to(handlers::delete_webhook))
+        .route("/webhooks/{id}/deliveries", web::get().to(handlers::get_webhook_deliveries))
+        .route("/webhooks/deliveries/{id}/redeliver", web::post().to(handlers::redeliver_webhook))
+    
+        // Device routes
+        .route("/devices", web::post().to(handlers::register_device))
+        .route("/devices/{id}/rotate", web::post().to(handlers::rotate_device_key))
+        .route("/devices/{id}/revoke", web::post().to(handlers::revoke_device))
+    
+        // GS1 routes
+        .route("/gs1/parse", web::post().to(handlers::parse_gs1))
+    
+        // Verification routes
+        .route("/verify/{token}", web::get().to(handlers::verify_shipment))
+        .route("/certificates/key", web::get().to(handlers::get_certificate_key))
+    
+        // Weather route
+        .route("/weather/{location}", web::get().to(handlers::get_weather))
+    
+        // Authentication routes
+        .route("/auth/verify", web::post().to(handlers::verify_password));
+}
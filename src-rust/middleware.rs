@@ -0,0 +1,257 @@
+use actix_web::{
+    body::{self, BoxBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::{header, StatusCode},
+    middleware::{DefaultHeaders, Next},
+    web, Error, HttpResponse,
+};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::models::StoredResponse;
+use crate::storage::{IdempotencyOutcome, Storage};
+use crate::wire::{self, CAMEL_JSON};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Response headers worth replaying alongside the stored body.
+const REPLAYED_HEADERS: [header::HeaderName; 2] = [header::CONTENT_TYPE, header::ETAG];
+
+/// Route middleware honouring the `Idempotency-Key` header. The first
+/// successful response for a key is stored and replayed for repeats; a repeat
+/// whose method, path or body differs is rejected. Failed requests are
+/// forgotten so the client can retry them with the same key.
+pub async fn idempotency(
+    storage: web::Data<Arc<Storage>>,
+    mut req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER).and_then(|value| value.to_str().ok()) {
+        Some(key) => key.to_string(),
+        None => return next.call(req).await,
+    };
+    
+    // Buffer the body to fingerprint it, then hand it back to the handler
+    let bytes = req.extract::<web::Bytes>().await?;
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str().as_bytes());
+    hasher.update(b" ");
+    hasher.update(req.path().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(&bytes);
+    let fingerprint = hex::encode(hasher.finalize());
+    req.set_payload(Payload::from(bytes));
+    
+    // Answer rejections as responses so outer middleware still sees them
+    let outcome = match storage.begin_idempotent_request(&key, &fingerprint) {
+        Ok(outcome) => outcome,
+        Err(err) => return Ok(req.error_response(err)),
+    };
+    match outcome {
+        IdempotencyOutcome::Replay(stored) => {
+            let mut response = HttpResponse::build(StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK));
+            for (name, value) in &stored.headers {
+                response.insert_header((name.as_str(), value.as_str()));
+            }
+            response.insert_header(("Idempotent-Replayed", "true"));
+            return Ok(req.into_response(response.body(stored.body)));
+        }
+        IdempotencyOutcome::Proceed => {}
+    }
+    
+    let mut claim = IdempotencyClaim { storage: &storage, key, completed: false };
+    let res = next.call(req).await?;
+    if !res.status().is_success() {
+        return Ok(res);
+    }
+    
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let body = body::to_bytes(body).await.map_err(Into::<Error>::into)?;
+    
+    claim.complete(StoredResponse {
+        status: res.status().as_u16(),
+        headers: REPLAYED_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = res.headers().get(name)?.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect(),
+        body: body.to_vec(),
+    });
+    
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(body))))
+}
+
+/// A claimed `Idempotency-Key`, released when dropped unless its response was
+/// stored. Covers failed requests as well as cancelled or panicking ones, which
+/// would otherwise hold the key until it expires.
+struct IdempotencyClaim<'a> {
+    storage: &'a Storage,
+    key: String,
+    completed: bool,
+}
+
+impl IdempotencyClaim<'_> {
+    fn complete(&mut self, response: StoredResponse) {
+        self.storage.complete_idempotent_request(&self.key, response);
+        self.completed = true;
+    }
+}
+
+impl Drop for IdempotencyClaim<'_> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.storage.abort_idempotent_request(&self.key);
+        }
+    }
+}
+
+/// Negotiates the wire format: bodies sent as `wire::CAMEL_JSON` are rewritten
+/// to the models' shape before handlers see them, and JSON responses are
+/// rewritten to camelCase when the client accepts it. Streams pass through.
+pub async fn wire_format(mut req: ServiceRequest, next: Next<BoxBody>) -> Result<ServiceResponse<BoxBody>, Error> {
+    let wants_camel = names_camel_json(req.headers(), header::ACCEPT);
+    
+    if names_camel_json(req.headers(), header::CONTENT_TYPE) {
+        let bytes = req.extract::<web::Bytes>().await?;
+        let bytes = match serde_json::from_slice(&bytes) {
+            Ok(value) => serde_json::to_vec(&wire::from_camel(value)).map(web::Bytes::from).unwrap_or(bytes),
+            // Leave malformed bodies for the JSON extractor to report
+            Err(_) => bytes,
+        };
+        req.set_payload(Payload::from(bytes));
+    }
+    
+    let res = next.call(req).await?;
+    
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        return Ok(res);
+    }
+    
+    let (req, mut res) = res.into_parts();
+    res.headers_mut().insert(header::VARY, header::HeaderValue::from_static("Accept"));
+    if !wants_camel {
+        return Ok(ServiceResponse::new(req, res));
+    }
+    
+    let (mut res, body) = res.into_parts();
+    let bytes = body::to_bytes(body).await.map_err(Into::<Error>::into)?;
+    let bytes = match serde_json::from_slice(&bytes) {
+        Ok(value) => serde_json::to_vec(&wire::to_camel(value)).map(web::Bytes::from).unwrap_or(bytes),
+        Err(_) => bytes,
+    };
+    res.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static(CAMEL_JSON));
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(bytes))))
+}
+
+/// Whether the `Accept` or `Content-Type` header `name` asks for the camelCase
+/// wire format. Streams, which `wire_format` passes through, check it themselves.
+pub fn names_camel_json(headers: &header::HeaderMap, name: header::HeaderName) -> bool {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains(CAMEL_JSON))
+}
+
+/// Marks every response of a superseded API version as deprecated and points
+/// clients at its successor.
+pub fn deprecated(successor: &str) -> DefaultHeaders {
+    DefaultHeaders::new()
+        .add(("Deprecation", "true"))
+        .add((header::LINK, format!("<{}>; rel=\"successor-version\"", successor)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{middleware::from_fn, test as actix_test, App};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    async fn counted(body: web::Bytes) -> HttpResponse {
+        let call = CALLS.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Created().insert_header((header::ETAG, "\"1\"")).body(format!("{} {}", call, String::from_utf8_lossy(&body)))
+    }
+
+    async fn stalled() -> HttpResponse {
+        std::future::pending::<()>().await;
+        HttpResponse::Ok().finish()
+    }
+
+    macro_rules! test_app {
+        ($storage:expr) => {
+            actix_test::init_service(
+                App::new()
+                    .app_data(web::Data::new($storage.clone()))
+                    .route("/counted", web::post().to(counted).wrap(from_fn(idempotency)))
+                    .route("/stalled", web::post().to(stalled).wrap(from_fn(idempotency))),
+            )
+            .await
+        };
+    }
+
+    fn keyed(uri: &str, key: &str, body: &'static str) -> actix_web::test::TestRequest {
+        actix_test::TestRequest::post()
+            .uri(uri)
+            .insert_header((IDEMPOTENCY_KEY_HEADER, key))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn repeats_replay_the_first_response() {
+        let storage = Arc::new(Storage::new());
+        let app = test_app!(storage);
+        
+        let first = actix_test::call_service(&app, keyed("/counted", "replay", "a").to_request()).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get("Idempotent-Replayed").is_none());
+        let first = actix_test::read_body(first).await;
+        
+        let repeat = actix_test::call_service(&app, keyed("/counted", "replay", "a").to_request()).await;
+        assert_eq!(repeat.status(), StatusCode::CREATED);
+        assert_eq!(repeat.headers().get("Idempotent-Replayed").unwrap(), "true");
+        assert_eq!(repeat.headers().get(header::ETAG).unwrap(), "\"1\"");
+        assert_eq!(actix_test::read_body(repeat).await, first, "the handler ran only once");
+    }
+
+    #[actix_web::test]
+    async fn reusing_a_key_for_another_request_is_rejected() {
+        let storage = Arc::new(Storage::new());
+        let app = test_app!(storage);
+        
+        let first = actix_test::call_service(&app, keyed("/counted", "reused", "a").to_request()).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        
+        let other = actix_test::call_service(&app, keyed("/counted", "reused", "b").to_request()).await;
+        assert_eq!(other.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_web::test]
+    async fn in_flight_keys_conflict_and_are_released_when_dropped() {
+        let storage = Arc::new(Storage::new());
+        let app = test_app!(storage);
+        
+        let stalled = actix_test::call_service(&app, keyed("/stalled", "in-flight", "a").to_request());
+        let repeat = async {
+            tokio::task::yield_now().await;
+            actix_test::call_service(&app, keyed("/stalled", "in-flight", "a").to_request()).await
+        };
+        let repeat = tokio::select! {
+            biased;
+            _ = stalled => unreachable!("the handler never finishes"),
+            res = repeat => res,
+        };
+        assert_eq!(repeat.status(), StatusCode::CONFLICT);
+        
+        // The stalled request was dropped with the select, freeing its key
+        assert!(storage.idempotency_keys.is_empty());
+    }
+}
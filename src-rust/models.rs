@@ -1,8 +1,13 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::gs1;
+use crate::storage::Storage;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Product {
     pub id: String,
     pub name: String,
@@ -10,11 +15,78 @@ pub struct Product {
     pub manufacturer: String,
     pub min_temperature: f64,
     pub max_temperature: f64,
+    /// Cumulative minutes the product may spend outside its temperature range
+    /// before a shipment is marked compromised. Zero means no tolerance.
+    #[serde(default)]
+    pub excursion_budget_minutes: f64,
+    /// Limits beyond temperature, e.g. humidity, light or shock.
+    #[serde(default)]
+    pub constraints: Vec<EnvironmentalConstraint>,
+    /// Default carrier for shipments that don't choose their own.
     pub logistics_partner: String,
+    /// GS1 trade item number, zero-padded to 14 digits.
+    #[serde(default)]
+    pub gtin: Option<String>,
+    /// List price per unit, used for line items that don't set their own.
+    #[serde(default)]
+    pub unit_price: Option<Money>,
     pub registered_at: DateTime<Utc>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Set when the product is deregistered. Deleted products stay readable so
+    /// existing shipments can still resolve them, but can't be shipped again.
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Product {
+    /// The product's temperature range followed by its other constraints.
+    pub fn all_constraints(&self) -> Vec<EnvironmentalConstraint> {
+        let temperature = EnvironmentalConstraint {
+            metric: EnvironmentalMetric::Temperature,
+            min: Some(self.min_temperature),
+            max: Some(self.max_temperature),
+        };
+        std::iter::once(temperature)
+            .chain(self.constraints.iter().cloned())
+            .collect()
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Measured quantities a product can be constrained on. Units are °C for
+/// temperature, % relative humidity, lux for light and g for shock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvironmentalMetric {
+    Temperature,
+    Humidity,
+    Light,
+    Shock,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EnvironmentalConstraint {
+    pub metric: EnvironmentalMetric,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    BelowMin,
+    AboveMax,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConstraintViolation {
+    pub metric: EnvironmentalMetric,
+    pub kind: ViolationKind,
+    pub value: f64,
+    pub limit: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ShipmentEvent {
     pub id: String,
     pub timestamp: DateTime<Utc>,
@@ -24,9 +96,73 @@ pub struct ShipmentEvent {
     pub reporter: String,
     pub event_type: EventType,
     pub is_temperature_valid: bool,
+    #[serde(default)]
+    pub humidity: Option<f64>,
+    #[serde(default)]
+    pub shock: Option<f64>,
+    #[serde(default)]
+    pub light: Option<f64>,
+    #[serde(default)]
+    pub violations: Vec<ConstraintViolation>,
+    /// Where the event was reported, when the reporter has a position fix.
+    #[serde(default)]
+    pub coordinates: Option<Coordinates>,
+    /// Links the event to the one before it, see `EventChainService`.
+    #[serde(default)]
+    pub hash: String,
+}
+
+/// A WGS 84 position in decimal degrees.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Validate, ToSchema)]
+pub struct Coordinates {
+    #[validate(range(min = -90.0, max = 90.0, message = "Latitude must be between -90 and 90"))]
+    pub latitude: f64,
+    #[validate(range(min = -180.0, max = 180.0, message = "Longitude must be between -180 and 180"))]
+    pub longitude: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// An amount of money in the minor unit of its currency (paise for INR),
+/// so escrow arithmetic is exact.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate, ToSchema)]
+pub struct Money {
+    #[validate(range(min = 1, message = "Amount must be positive"))]
+    pub amount_minor: i64,
+    /// ISO 4217 currency code.
+    #[validate(custom(function = "validate_currency"))]
+    pub currency: String,
+}
+
+impl Money {
+    pub const DEFAULT_CURRENCY: &'static str = "INR";
+    
+    /// Converts a decimal amount in major units, as v1 clients send it.
+    pub fn from_major(amount: f64, currency: &str) -> Self {
+        Self {
+            amount_minor: (amount * 100.0).round() as i64,
+            currency: currency.to_string(),
+        }
+    }
+    
+    pub fn to_major(&self) -> f64 {
+        self.amount_minor as f64 / 100.0
+    }
+    
+    /// The price of `quantity` units at this unit price, rounded to the minor
+    /// unit, or `None` if it doesn't fit.
+    pub fn checked_times(&self, quantity: f64) -> Option<Self> {
+        let amount = (self.amount_minor as f64 * quantity).round();
+        // i64::MAX isn't representable as f64; its neighbour 2^63 is the first value out of range
+        if !amount.is_finite() || amount < i64::MIN as f64 || amount >= i64::MAX as f64 {
+            return None;
+        }
+        Some(Self {
+            amount_minor: amount as i64,
+            currency: self.currency.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum EventType {
     Pickup,
@@ -34,24 +170,301 @@ pub enum EventType {
     Delivery,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Shipment {
     pub id: String,
+    /// The first line item's product, for clients that expect one product per shipment.
     pub product_id: String,
     pub product: Product,
     pub manufacturer: String,
     pub logistics_partner: String,
     pub consumer: String,
     pub status: ShipmentStatus,
-    pub escrow_amount: f64,
+    pub escrow: Money,
     pub escrow_released: bool,
+    /// Escrow paid back to the consumer after a cancellation or return. Any
+    /// remainder is released to the manufacturer.
+    #[serde(default)]
+    pub escrow_refund: Option<Money>,
+    pub line_items: Vec<LineItem>,
     pub events: Vec<ShipmentEvent>,
+    /// Open recalls covering the shipment. Escrow is frozen while any remain.
+    #[serde(default)]
+    pub recalls: Vec<String>,
+    /// The carriers' legs of the route, in order. `logistics_partner` is the
+    /// partner of the leg in progress.
+    #[serde(default)]
+    pub legs: Vec<Leg>,
+    /// Index into `legs` of the leg in progress when the shipment was
+    /// compromised, or null if it happened before pickup or hasn't happened.
+    #[serde(default)]
+    pub compromised_leg: Option<usize>,
+    /// When the cold chain was first broken, outbound or on a return. Unlike
+    /// the status, it survives the shipment being returned.
+    #[serde(default)]
+    pub compromised_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub cancellation: Option<Cancellation>,
+    #[serde(default)]
+    pub return_details: Option<ReturnDetails>,
     pub created_at: DateTime<Utc>,
     pub delivered_at: Option<DateTime<Utc>>,
     pub confirmed_at: Option<DateTime<Utc>>,
+    /// Incremented on every stored update; used for optimistic concurrency.
+    #[serde(default)]
+    pub version: u64,
+}
+
+impl Shipment {
+    /// Whether `user_id` takes part in this shipment in the given role
+    /// (`manufacturer`, `logistics` or `consumer`).
+    pub fn involves(&self, user_id: &str, role: &str) -> bool {
+        match role {
+            "manufacturer" => self.manufacturer == user_id,
+            "logistics" => self.logistics_partner == user_id || self.legs.iter().any(|leg| leg.partner == user_id),
+            "consumer" => self.consumer == user_id,
+            _ => false,
+        }
+    }
+    
+    /// Index of the leg in progress: the first one not yet ended.
+    pub fn current_leg(&self) -> usize {
+        self.legs
+            .iter()
+            .position(|leg| leg.ended_at.is_none())
+            .unwrap_or(self.legs.len().saturating_sub(1))
+    }
+    
+    /// Index of the leg that had custody at `timestamp`, if the goods were
+    /// with a carrier then.
+    pub fn leg_at(&self, timestamp: DateTime<Utc>) -> Option<usize> {
+        self.legs.iter().position(|leg| {
+            leg.started_at.is_some_and(|started| started <= timestamp)
+                && leg.ended_at.is_none_or(|ended| timestamp <= ended)
+        })
+    }
+    
+    /// Who holds the goods, going by the last custody event: the manufacturer
+    /// until pickup, the consumer after delivery and the carrier in between.
+    pub fn custodian(&self) -> &str {
+        match self.events.last().map(|event| &event.event_type) {
+            None => &self.manufacturer,
+            Some(EventType::Delivery) => &self.consumer,
+            Some(_) => &self.logistics_partner,
+        }
+    }
+    
+    /// The strictest limits across every product in the shipment, as a product
+    /// the telemetry checks can take: the narrowest range for each metric and
+    /// the smallest excursion budget.
+    pub fn limits(&self) -> Product {
+        let mut limits = self.product.clone();
+        let mut constraints: Vec<EnvironmentalConstraint> = Vec::new();
+        for product in self.line_items.iter().map(|item| &item.product) {
+            limits.excursion_budget_minutes = limits.excursion_budget_minutes.min(product.excursion_budget_minutes);
+            for constraint in product.all_constraints() {
+                match constraints.iter_mut().find(|existing| existing.metric == constraint.metric) {
+                    Some(existing) => {
+                        existing.min = match (existing.min, constraint.min) {
+                            (Some(a), Some(b)) => Some(a.max(b)),
+                            (a, b) => a.or(b),
+                        };
+                        existing.max = match (existing.max, constraint.max) {
+                            (Some(a), Some(b)) => Some(a.min(b)),
+                            (a, b) => a.or(b),
+                        };
+                    }
+                    None => constraints.push(constraint),
+                }
+            }
+        }
+        
+        if let Some(temperature) = constraints.iter().position(|c| c.metric == EnvironmentalMetric::Temperature) {
+            let temperature = constraints.remove(temperature);
+            limits.min_temperature = temperature.min.unwrap_or(limits.min_temperature);
+            limits.max_temperature = temperature.max.unwrap_or(limits.max_temperature);
+        }
+        limits.constraints = constraints;
+        limits
+    }
+}
+
+/// A stretch of the route carried by one logistics partner.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Leg {
+    pub partner: String,
+    /// Pickup for the first leg, otherwise the handoff from the previous leg.
+    pub started_at: Option<DateTime<Utc>>,
+    /// Handoff to the next leg, or delivery for the last.
+    pub ended_at: Option<DateTime<Utc>>,
+    /// Handover to the next leg's partner, once either side has acknowledged it.
+    pub handoff: Option<Handoff>,
+}
+
+impl Leg {
+    pub fn new(partner: String) -> Self {
+        Self {
+            partner,
+            started_at: None,
+            ended_at: None,
+            handoff: None,
+        }
+    }
+}
+
+/// Custody passes between legs only once both partners have acknowledged it.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Handoff {
+    pub location: String,
+    /// When the outgoing partner acknowledged releasing the goods.
+    pub released_at: Option<DateTime<Utc>>,
+    /// When the incoming partner acknowledged receiving them.
+    pub received_at: Option<DateTime<Utc>>,
+}
+
+/// Units a line item's quantity can be counted or measured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitOfMeasure {
+    #[default]
+    Each,
+    Kilogram,
+    Litre,
+}
+
+impl UnitOfMeasure {
+    /// UN/ECE Recommendation 20 code, as EPCIS expects for measured quantities.
+    /// Counted units have none.
+    pub fn un_code(&self) -> Option<&'static str> {
+        match self {
+            UnitOfMeasure::Each => None,
+            UnitOfMeasure::Kilogram => Some("KGM"),
+            UnitOfMeasure::Litre => Some("LTR"),
+        }
+    }
+}
+
+/// One product, and optionally one lot of it, in a shipment.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LineItem {
+    pub product_id: String,
+    /// The product as it was when the shipment was funded.
+    pub product: Product,
+    /// GS1 batch/lot number (AI 10).
+    pub lot: Option<String>,
+    /// Expiry date of the lot (AI 17).
+    pub expiry: Option<NaiveDate>,
+    /// Serial numbers (AI 21) of the individual units, which together with the
+    /// product's GTIN form their SGTINs.
+    #[serde(default)]
+    pub serials: Vec<String>,
+    pub quantity: f64,
+    pub unit: UnitOfMeasure,
+    /// Agreed price per unit, if the item is priced.
+    pub unit_price: Option<Money>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RecallStatus {
+    Open,
+    Closed,
+}
+
+/// A manufacturer's recall of a product, optionally narrowed to one lot and
+/// to shipments created within a date range.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Recall {
+    pub id: String,
+    pub product_id: String,
+    pub lot: Option<String>,
+    /// First day of shipments covered, inclusive (UTC).
+    pub shipped_from: Option<NaiveDate>,
+    /// Last day of shipments covered, inclusive (UTC).
+    pub shipped_until: Option<NaiveDate>,
+    pub reason: String,
+    pub issued_by: String,
+    pub status: RecallStatus,
+    /// Every shipment the recall flagged when it was issued.
+    pub shipment_ids: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+impl Recall {
+    /// Whether a line item shipped on `shipped_on` falls within the recall.
+    pub fn covers(&self, item: &LineItem, shipped_on: NaiveDate) -> bool {
+        item.product_id == self.product_id
+            && self.lot.as_ref().is_none_or(|lot| item.lot.as_ref() == Some(lot))
+            && self.shipped_from.is_none_or(|from| shipped_on >= from)
+            && self.shipped_until.is_none_or(|until| shipped_on <= until)
+    }
+    
+    pub fn covered_items<'a>(&'a self, shipment: &'a Shipment) -> impl Iterator<Item = &'a LineItem> {
+        let shipped_on = shipment.created_at.date_naive();
+        shipment.line_items.iter().filter(move |item| self.covers(item, shipped_on))
+    }
+}
+
+/// A recall and where each affected shipment is now.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RecallReport {
+    pub recall: Recall,
+    pub shipments: Vec<RecalledShipment>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RecalledShipment {
+    pub shipment_id: String,
+    pub status: ShipmentStatus,
+    /// Whoever holds the goods now, see `Shipment::custodian`.
+    pub custodian: String,
+    /// Location of the last custody event, if there has been one.
+    pub last_location: Option<String>,
+    pub consumer: String,
+    pub lots: Vec<String>,
+    pub serials: Vec<String>,
+    pub quantity: f64,
+    pub unit: UnitOfMeasure,
+    pub escrow_frozen: bool,
+}
+
+/// A lot of a product and how much of it has been shipped.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LotSummary {
+    pub lot: String,
+    pub expiry: Option<NaiveDate>,
+    pub quantity: f64,
+    pub unit: UnitOfMeasure,
+    pub shipment_count: usize,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Every shipment carrying a lot of a product, oldest first.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LotTrace {
+    pub product_id: String,
+    pub lot: String,
+    pub expiry: Option<NaiveDate>,
+    pub quantity: f64,
+    pub unit: UnitOfMeasure,
+    pub shipments: Vec<LotShipment>,
+}
+
+/// How much of a lot one shipment carries and where that shipment is.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LotShipment {
+    pub shipment_id: String,
+    pub status: ShipmentStatus,
+    pub quantity: f64,
+    pub serials: Vec<String>,
+    pub manufacturer: String,
+    pub logistics_partner: String,
+    pub consumer: String,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ShipmentStatus {
     Pending,
@@ -59,9 +472,258 @@ pub enum ShipmentStatus {
     Compromised,
     Delivered,
     Confirmed,
+    Cancelled,
+    /// On its way back to the manufacturer.
+    Returning,
+    Returned,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Cancellation {
+    pub reason: String,
+    pub requested_by: String,
+    pub cancelled_at: DateTime<Utc>,
+}
+
+/// Why goods are sent back, which decides how much escrow the consumer gets back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnReason {
+    /// The consumer refused or no longer wants sound goods.
+    Refused,
+    Damaged,
+    WrongItem,
+    Recalled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReturnDetails {
+    pub reason: ReturnReason,
+    pub details: Option<String>,
+    pub requested_by: String,
+    pub requested_at: DateTime<Utc>,
+    /// The shipment's status when the return was requested.
+    pub returned_from: ShipmentStatus,
+    /// When the manufacturer took the goods back.
+    pub returned_at: Option<DateTime<Utc>>,
+    /// Whether the goods stayed within the product's limits on the way back.
+    pub cold_chain_intact: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TelemetryReading {
+    pub timestamp: DateTime<Utc>,
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub shock: Option<f64>,
+    pub light: Option<f64>,
+}
+
+impl TelemetryReading {
+    pub fn value(&self, metric: EnvironmentalMetric) -> Option<f64> {
+        match metric {
+            EnvironmentalMetric::Temperature => self.temperature,
+            EnvironmentalMetric::Humidity => self.humidity,
+            EnvironmentalMetric::Light => self.light,
+            EnvironmentalMetric::Shock => self.shock,
+        }
+    }
+}
+
+/// Compact in-memory form of a `TelemetryReading`: epoch millis plus `f32`
+/// channels, with `NaN` standing in for channels the logger didn't report.
+#[derive(Debug, Clone, Copy)]
+pub struct TelemetrySample {
+    pub timestamp_ms: i64,
+    pub temperature: f32,
+    pub humidity: f32,
+    pub shock: f32,
+    pub light: f32,
+}
+
+impl From<&TelemetryReading> for TelemetrySample {
+    fn from(reading: &TelemetryReading) -> Self {
+        let pack = |value: Option<f64>| value.map(|v| v as f32).unwrap_or(f32::NAN);
+        Self {
+            timestamp_ms: reading.timestamp.timestamp_millis(),
+            temperature: pack(reading.temperature),
+            humidity: pack(reading.humidity),
+            shock: pack(reading.shock),
+            light: pack(reading.light),
+        }
+    }
+}
+
+impl From<&TelemetrySample> for TelemetryReading {
+    fn from(sample: &TelemetrySample) -> Self {
+        let unpack = |value: f32| if value.is_nan() { None } else { Some(value as f64) };
+        Self {
+            timestamp: DateTime::from_timestamp_millis(sample.timestamp_ms).unwrap_or_default(),
+            temperature: unpack(sample.temperature),
+            humidity: unpack(sample.humidity),
+            shock: unpack(sample.shock),
+            light: unpack(sample.light),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TelemetryExcursion {
+    pub timestamp: DateTime<Utc>,
+    pub violations: Vec<ConstraintViolation>,
+}
+
+/// A change to a shipment, broadcast to live subscribers as it is stored.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ShipmentUpdate {
+    pub shipment_id: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub change: ShipmentChange,
+    pub shipment: Shipment,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShipmentChange {
+    Created,
+    StatusChanged { from: ShipmentStatus, to: ShipmentStatus },
+    EventAdded { event: ShipmentEvent },
+    EscrowReleased { amount: Money },
+    EscrowRefunded { amount: Money },
+    Recalled { recall_id: String },
+}
+
+impl ShipmentChange {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShipmentChange::Created => "created",
+            ShipmentChange::StatusChanged { .. } => "status_changed",
+            ShipmentChange::EventAdded { .. } => "event_added",
+            ShipmentChange::EscrowReleased { .. } => "escrow_released",
+            ShipmentChange::EscrowRefunded { .. } => "escrow_refunded",
+            ShipmentChange::Recalled { .. } => "recalled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ThermalSummary {
+    pub shipment_id: String,
+    pub reading_count: usize,
+    pub first_reading_at: Option<DateTime<Utc>>,
+    pub last_reading_at: Option<DateTime<Utc>>,
+    pub mean_kinetic_temperature: Option<f64>,
+    pub min_temperature: Option<f64>,
+    pub max_temperature: Option<f64>,
+    pub minutes_above_max: f64,
+    pub minutes_below_min: f64,
+    pub excursion_budget_minutes: f64,
+    pub budget_exceeded: bool,
+    /// When the excursions ran through the budget, if they have.
+    pub budget_exceeded_at: Option<DateTime<Utc>>,
+}
+
+/// What anyone holding a shipment's verification token may see. Escrow,
+/// internal ids and the logistics and consumer parties are left out.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VerificationSummary {
+    pub items: Vec<VerifiedItem>,
+    /// Manufacturer name.
+    pub manufacturer: Option<String>,
+    pub status: ShipmentStatus,
+    /// No custody event breached the product's limits.
+    pub cold_chain_intact: bool,
+    /// The custody events still match their hash chain.
+    pub record_intact: bool,
+    pub shipped_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub journey: Vec<VerifiedEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VerifiedItem {
+    pub gtin: Option<String>,
+    pub name: String,
+    pub description: String,
+    pub lot: Option<String>,
+    pub expiry: Option<NaiveDate>,
+    pub quantity: f64,
+    pub unit: UnitOfMeasure,
+    pub min_temperature: f64,
+    pub max_temperature: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VerifiedEvent {
+    pub event_type: EventType,
+    pub location: String,
+    pub timestamp: DateTime<Utc>,
+    pub within_limits: bool,
+}
+
+/// GS1 identification of a trade item, a lot of it or a single serialized unit (SGTIN).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Gs1Identifiers {
+    /// GTIN-14 (AI 01).
+    pub gtin: String,
+    /// Batch/lot (AI 10).
+    pub lot: Option<String>,
+    /// Expiry date (AI 17).
+    pub expiry: Option<NaiveDate>,
+    /// Serial number (AI 21).
+    pub serial: Option<String>,
+}
+
+/// One label's worth of GS1 data: per serialized unit, or per lot for unserialized shipments.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Gs1Label {
+    pub identifiers: Gs1Identifiers,
+    pub digital_link: String,
+    /// Human-readable GS1-128 text, e.g. `(01)09506000134352(10)LOT1`.
+    pub element_string: String,
+    /// GS1-128 barcode data with group separators (U+001D) after variable-length elements.
+    pub barcode_data: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct Gs1ParseRequest {
+    /// A Digital Link URI, bracketed element string or raw scanned barcode data.
+    #[validate(length(min = 1, message = "Data must not be empty"))]
+    pub data: String,
+}
+
+/// Signed contents of a shipment certificate. An inspector who trusts the
+/// signing key learns the public summary and the head of the event hash chain,
+/// which later copies of the custody record can be checked against.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CertificateClaims {
+    pub issuer: String,
+    pub issued_at: DateTime<Utc>,
+    pub summary: VerificationSummary,
+    /// Hash of the latest custody event when the certificate was issued.
+    pub chain_head: String,
+    pub event_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ShipmentCertificate {
+    /// Compact JWS (`header.payload.signature`) over the claims, signed with EdDSA/Ed25519.
+    pub certificate: String,
+    pub claims: CertificateClaims,
+}
+
+/// The published half of the certificate signing key.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CertificateKey {
+    /// Key id carried in the `kid` header of every certificate.
+    pub kid: String,
+    pub algorithm: String,
+    /// Raw 32-byte Ed25519 public key, base64url without padding.
+    pub public_key: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct User {
     pub id: String,
     pub name: String,
@@ -69,7 +731,7 @@ pub struct User {
     pub address: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum UserRole {
     Manufacturer,
@@ -77,45 +739,481 @@ pub enum UserRole {
     Consumer,
 }
 
+impl UserRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserRole::Manufacturer => "manufacturer",
+            UserRole::Logistics => "logistics",
+            UserRole::Consumer => "consumer",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum WebhookEventType {
+    #[serde(rename = "shipment.created")]
+    ShipmentCreated,
+    #[serde(rename = "shipment.status_changed")]
+    ShipmentStatusChanged,
+    #[serde(rename = "shipment.compromised")]
+    ShipmentCompromised,
+    #[serde(rename = "shipment.event_added")]
+    ShipmentEventAdded,
+    #[serde(rename = "escrow.released")]
+    EscrowReleased,
+    #[serde(rename = "shipment.recalled")]
+    ShipmentRecalled,
+    #[serde(rename = "escrow.refunded")]
+    EscrowRefunded,
+}
+
+impl WebhookEventType {
+    pub fn from_change(change: &ShipmentChange) -> Self {
+        match change {
+            ShipmentChange::Created => WebhookEventType::ShipmentCreated,
+            ShipmentChange::StatusChanged { to: ShipmentStatus::Compromised, .. } => WebhookEventType::ShipmentCompromised,
+            ShipmentChange::StatusChanged { .. } => WebhookEventType::ShipmentStatusChanged,
+            ShipmentChange::EventAdded { .. } => WebhookEventType::ShipmentEventAdded,
+            ShipmentChange::EscrowReleased { .. } => WebhookEventType::EscrowReleased,
+            ShipmentChange::EscrowRefunded { .. } => WebhookEventType::EscrowRefunded,
+            ShipmentChange::Recalled { .. } => WebhookEventType::ShipmentRecalled,
+        }
+    }
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::ShipmentCreated => "shipment.created",
+            WebhookEventType::ShipmentStatusChanged => "shipment.status_changed",
+            WebhookEventType::ShipmentCompromised => "shipment.compromised",
+            WebhookEventType::ShipmentEventAdded => "shipment.event_added",
+            WebhookEventType::EscrowReleased => "escrow.released",
+            WebhookEventType::ShipmentRecalled => "shipment.recalled",
+            WebhookEventType::EscrowRefunded => "escrow.refunded",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookSubscription {
+    pub id: String,
+    pub user_id: String,
+    pub url: String,
+    pub events: Vec<WebhookEventType>,
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeliveryAttempt {
+    pub attempted_at: DateTime<Utc>,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub subscription_id: String,
+    pub event_type: WebhookEventType,
+    pub payload: serde_json::Value,
+    pub status: DeliveryStatus,
+    /// Attempts since the delivery was (re)queued; drives the retry backoff.
+    pub retries: u32,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub attempts: Vec<DeliveryAttempt>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A response captured for an `Idempotency-Key`, replayed verbatim on repeats.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub fingerprint: String,
+    pub created_at: DateTime<Utc>,
+    /// `None` while the first request is still being handled.
+    pub response: Option<StoredResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    pub key_prefix: String,
+    #[serde(skip)]
+    pub key_hash: String,
+    pub status: DeviceStatus,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+    Active,
+    Revoked,
+}
+
 // Request/Response DTOs
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[validate(context = "Storage", schema(function = "validate_product_limits", skip_on_field_errors = false))]
 pub struct RegisterProductRequest {
+    #[validate(length(min = 1, message = "Name must not be empty"))]
     pub name: String,
     pub description: String,
+    #[validate(custom(function = "validate_manufacturer", use_context))]
     pub manufacturer: String,
     pub min_temperature: f64,
     pub max_temperature: f64,
+    #[serde(default)]
+    #[validate(range(min = 0.0, message = "Excursion budget must not be negative"))]
+    pub excursion_budget_minutes: f64,
+    #[serde(default)]
+    pub constraints: Vec<EnvironmentalConstraint>,
+    /// Default carrier; each shipment may choose another.
+    #[validate(custom(function = "validate_logistics_partner", use_context))]
     pub logistics_partner: String,
+    /// GTIN-8, -12, -13 or -14; stored as GTIN-14.
+    #[validate(custom(function = "validate_gtin"))]
+    pub gtin: Option<String>,
+    #[validate(nested)]
+    pub unit_price: Option<Money>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct UpdateProductRequest {
+    #[validate(length(min = 1, message = "Name must not be empty"))]
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[validate(context = "Storage", schema(function = "validate_route", skip_on_field_errors = false))]
 pub struct FundEscrowRequest {
-    pub product_id: String,
+    #[validate(custom(function = "validate_consumer", use_context))]
     pub consumer: String,
-    pub escrow_amount: f64,
+    /// Computed from the line items when omitted, which requires every item
+    /// to be priced. When given, it must match their total if every item is
+    /// priced, and otherwise cover the items that are.
+    #[validate(nested)]
+    pub escrow: Option<Money>,
+    #[validate(length(min = 1, message = "A shipment needs at least one line item"), nested)]
+    pub line_items: Vec<LineItemRequest>,
+    /// Carrier for the whole route, instead of `legs`.
+    #[validate(custom(function = "validate_logistics_partner", use_context))]
+    pub logistics_partner: Option<String>,
+    /// Logistics partner of each leg of the route, in order. Without these or
+    /// `logistics_partner`, the first product's partner carries it all the way.
+    #[serde(default)]
+    #[validate(custom(function = "validate_leg_partners", use_context))]
+    pub legs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[validate(context = "Storage")]
+pub struct AssignPartnerRequest {
+    #[validate(custom(function = "validate_logistics_partner", use_context))]
+    pub logistics_partner: String,
+    /// Index of the leg to reassign; the first by default.
+    #[serde(default)]
+    pub leg: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct CancelShipmentRequest {
+    #[validate(length(min = 1, max = 500, message = "Reason must be 1-500 characters"))]
+    pub reason: String,
+    /// The shipment's manufacturer or consumer.
+    #[validate(length(min = 1, message = "Requester must not be empty"))]
+    pub requested_by: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ReturnShipmentRequest {
+    pub reason: ReturnReason,
+    #[validate(length(max = 500, message = "Details must be at most 500 characters"))]
+    pub details: Option<String>,
+    /// The shipment's manufacturer or consumer.
+    #[validate(length(min = 1, message = "Requester must not be empty"))]
+    pub requested_by: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[validate(context = "Storage")]
+pub struct HandoffRequest {
+    /// Index of the leg ending at the handoff.
+    pub leg: usize,
+    /// The leg's partner or the next leg's; each must acknowledge.
+    #[validate(custom(function = "validate_logistics_partner", use_context))]
+    pub user_id: String,
+    #[validate(length(min = 1, message = "Location must not be empty"))]
+    pub location: String,
+}
+
+// Serialize lets validator echo the rejected list when `line_items` is empty
+#[derive(Debug, Serialize, Deserialize, Validate, ToSchema)]
+#[validate(schema(function = "validate_line_item", skip_on_field_errors = false))]
+pub struct LineItemRequest {
+    #[validate(length(min = 1, message = "Product id must not be empty"))]
+    pub product_id: String,
+    /// Requires a product with a GTIN, as do `expiry` and `serials`.
+    #[validate(custom(function = "validate_gs1_attribute"))]
+    pub lot: Option<String>,
+    pub expiry: Option<NaiveDate>,
+    /// One per unit; requires `unit` to be `each`.
+    #[serde(default)]
+    #[validate(custom(function = "validate_serials"))]
+    pub serials: Vec<String>,
+    #[validate(range(exclusive_min = 0.0, message = "Quantity must be positive"))]
+    pub quantity: f64,
+    #[serde(default)]
+    pub unit: UnitOfMeasure,
+    /// Defaults to the product's list price.
+    #[validate(nested)]
+    pub unit_price: Option<Money>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[validate(context = "Storage", schema(function = "validate_recall_scope", skip_on_field_errors = false))]
+pub struct CreateRecallRequest {
+    #[validate(length(min = 1, message = "Product id must not be empty"))]
+    pub product_id: String,
+    /// Limits the recall to one lot; omit to recall every lot.
+    #[validate(custom(function = "validate_gs1_attribute"))]
+    pub lot: Option<String>,
+    pub shipped_from: Option<NaiveDate>,
+    pub shipped_until: Option<NaiveDate>,
+    #[validate(length(min = 1, max = 500, message = "Reason must be 1-500 characters"))]
+    pub reason: String,
+    /// Must be the product's manufacturer.
+    #[validate(custom(function = "validate_manufacturer", use_context))]
+    pub issued_by: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
 pub struct AddEventRequest {
+    #[validate(length(min = 1, message = "Location must not be empty"))]
     pub location: String,
     pub temperature: f64,
     pub event_type: EventType,
+    #[serde(default)]
     pub reporter: String,
+    #[validate(range(min = 0.0, max = 100.0, message = "Humidity must be between 0 and 100"))]
+    pub humidity: Option<f64>,
+    #[validate(range(min = 0.0, message = "Shock must not be negative"))]
+    pub shock: Option<f64>,
+    #[validate(range(min = 0.0, message = "Light must not be negative"))]
+    pub light: Option<f64>,
+    #[validate(nested)]
+    pub coordinates: Option<Coordinates>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct TelemetryBatchRequest {
+    #[validate(length(min = 1, message = "At least one reading is required"))]
+    pub readings: Vec<TelemetryReading>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TelemetryBatchResponse {
+    pub accepted: usize,
+    pub excursions: Vec<TelemetryExcursion>,
+    pub status: ShipmentStatus,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TelemetryQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    CreatedAt,
+    RegisteredAt,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Query string shared by the list endpoints. Filters that don't apply to a
+/// resource are ignored; `from`/`to` bound its primary timestamp.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+    pub sort: Option<SortField>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub status: Option<ShipmentStatus>,
+    pub manufacturer: Option<String>,
+    pub logistics_partner: Option<String>,
+    pub consumer: Option<String>,
+    pub role: Option<UserRole>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Shipments that were ever compromised, including ones since returned.
+    #[serde(default)]
+    pub compromised_only: bool,
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+impl ListQuery {
+    pub const DEFAULT_LIMIT: usize = 50;
+    pub const MAX_LIMIT: usize = 200;
+    
+    pub fn in_range(&self, timestamp: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| timestamp >= from) && self.to.is_none_or(|to| timestamp <= to)
+    }
+    
+    pub fn matches_shipment(&self, shipment: &Shipment) -> bool {
+        self.status.as_ref().is_none_or(|status| shipment.status == *status)
+            && self.manufacturer.as_ref().is_none_or(|id| shipment.manufacturer == *id)
+            && self.logistics_partner.as_ref().is_none_or(|id| shipment.involves(id, "logistics"))
+            && self.consumer.as_ref().is_none_or(|id| shipment.consumer == *id)
+            // Returns keep `compromised_at` after leaving the compromised status
+            && (!self.compromised_only || shipment.status == ShipmentStatus::Compromised || shipment.compromised_at.is_some())
+            && self.in_range(shipment.created_at)
+    }
+    
+    pub fn matches_product(&self, product: &Product) -> bool {
+        (self.include_deleted || product.deleted_at.is_none())
+            && self.manufacturer.as_ref().is_none_or(|id| product.manufacturer == *id)
+            && self.logistics_partner.as_ref().is_none_or(|id| product.logistics_partner == *id)
+            && self.in_range(product.registered_at)
+    }
+    
+    pub fn matches_user(&self, user: &User) -> bool {
+        self.role.as_ref().is_none_or(|role| user.role == *role)
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+#[derive(Debug, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QrQuery {
+    #[serde(default)]
+    pub format: QrFormat,
+    /// Minimum width and height in pixels.
+    #[validate(range(min = 64, max = 1024, message = "Size must be between 64 and 1024 pixels"))]
+    pub size: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    pub shipment_id: Option<String>,
+    pub user_id: Option<String>,
+    pub role: Option<String>,
+}
+
+impl StreamQuery {
+    pub fn matches(&self, update: &ShipmentUpdate) -> bool {
+        if let Some(shipment_id) = &self.shipment_id {
+            if *shipment_id != update.shipment_id {
+                return false;
+            }
+        }
+        match (&self.user_id, &self.role) {
+            (Some(user_id), Some(role)) => update.shipment.involves(user_id, role),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[validate(context = "Storage")]
+pub struct RegisterDeviceRequest {
+    #[validate(length(min = 1, message = "Name must not be empty"))]
+    pub name: String,
+    #[validate(custom(function = "validate_logistics_partner", use_context))]
+    pub owner: String,
+}
+
+// The plaintext key is only ever returned here, on registration and rotation
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceCredentials {
+    pub device: Device,
+    pub api_key: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct CreateWebhookRequest {
+    #[validate(custom(function = "validate_webhook_url"))]
+    pub url: String,
+    #[validate(length(min = 1, message = "At least one event type is required"))]
+    pub events: Vec<WebhookEventType>,
+}
+
+// The signing secret is only ever returned here, on creation
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebhookCredentials {
+    pub subscription: WebhookSubscription,
+    pub secret: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct VerifyPasswordRequest {
     pub password: String,
     pub action: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct VerifyPasswordResponse {
     pub valid: bool,
     pub message: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct WeatherResponse {
     pub temperature: f64,
     pub humidity: f64,
@@ -124,11 +1222,149 @@ pub struct WeatherResponse {
     pub location: String,
 }
 
-#[derive(Debug, Serialize)]
+// Validation rules shared by the request DTOs above
+
+fn field_error(code: &'static str, message: String) -> ValidationError {
+    ValidationError::new(code).with_message(message.into())
+}
+
+fn validate_user_role(id: &str, role: UserRole, storage: &Storage) -> Result<(), ValidationError> {
+    let user = storage
+        .get_user(id)
+        .ok_or_else(|| field_error("unknown_user", format!("User {} does not exist", id)))?;
+    if user.role != role {
+        return Err(field_error("wrong_role", format!("User {} does not have the {} role", id, role.as_str())));
+    }
+    Ok(())
+}
+
+fn validate_manufacturer(id: &str, storage: &Storage) -> Result<(), ValidationError> {
+    validate_user_role(id, UserRole::Manufacturer, storage)
+}
+
+fn validate_logistics_partner(id: &str, storage: &Storage) -> Result<(), ValidationError> {
+    validate_user_role(id, UserRole::Logistics, storage)
+}
+
+fn validate_leg_partners(ids: &[String], storage: &Storage) -> Result<(), ValidationError> {
+    ids.iter().try_for_each(|id| validate_logistics_partner(id, storage))?;
+    // A partner can't hand goods off to itself, and would acknowledge both sides at once
+    if ids.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(field_error("repeated_partner", "Consecutive legs must have different logistics partners".to_string()));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_consumer(id: &str, storage: &Storage) -> Result<(), ValidationError> {
+    validate_user_role(id, UserRole::Consumer, storage)
+}
+
+fn validate_product_limits(req: &RegisterProductRequest) -> Result<(), ValidationError> {
+    if req.min_temperature > req.max_temperature {
+        return Err(field_error(
+            "invalid_range",
+            "min_temperature must not exceed max_temperature".to_string(),
+        ));
+    }
+    for constraint in &req.constraints {
+        if let (Some(min), Some(max)) = (constraint.min, constraint.max) {
+            if min > max {
+                return Err(field_error(
+                    "invalid_range",
+                    format!("{:?} constraint min must not exceed max", constraint.metric),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_currency(currency: &str) -> Result<(), ValidationError> {
+    if currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase()) {
+        Ok(())
+    } else {
+        Err(field_error("invalid_currency", "Currency must be an ISO 4217 code".to_string()))
+    }
+}
+
+fn validate_gtin(gtin: &str) -> Result<(), ValidationError> {
+    gs1::normalize_gtin(gtin)
+        .map(|_| ())
+        .map_err(|e| field_error("invalid_gtin", e.to_string()))
+}
+
+fn validate_gs1_attribute(value: &str) -> Result<(), ValidationError> {
+    if gs1::is_valid_attribute(value) {
+        Ok(())
+    } else {
+        Err(field_error("invalid_gs1_attribute", "Must be 1-20 characters from the GS1 character set".to_string()))
+    }
+}
+
+fn validate_serials(serials: &[String]) -> Result<(), ValidationError> {
+    for (i, serial) in serials.iter().enumerate() {
+        validate_gs1_attribute(serial)?;
+        if serials[..i].contains(serial) {
+            return Err(field_error("duplicate_serial", format!("Serial {} is listed twice", serial)));
+        }
+    }
+    Ok(())
+}
+
+fn validate_line_item(item: &LineItemRequest) -> Result<(), ValidationError> {
+    if item.unit == UnitOfMeasure::Each && item.quantity.fract() != 0.0 {
+        return Err(field_error("invalid_quantity", "Counted items need a whole quantity".to_string()));
+    }
+    if !item.serials.is_empty() && (item.unit != UnitOfMeasure::Each || item.serials.len() as f64 != item.quantity) {
+        return Err(field_error("serial_count_mismatch", "Serials must list every unit of a counted item".to_string()));
+    }
+    Ok(())
+}
+
+fn validate_recall_scope(req: &CreateRecallRequest) -> Result<(), ValidationError> {
+    if let (Some(from), Some(until)) = (req.shipped_from, req.shipped_until) {
+        if from > until {
+            return Err(field_error("invalid_range", "shipped_from must not be after shipped_until".to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn validate_route(req: &FundEscrowRequest) -> Result<(), ValidationError> {
+    if req.logistics_partner.is_some() && !req.legs.is_empty() {
+        return Err(field_error("conflicting_route", "Give either logistics_partner or legs, not both".to_string()));
+    }
+    Ok(())
+}
+
+fn validate_webhook_url(url: &str) -> Result<(), ValidationError> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        Ok(())
+    } else {
+        Err(field_error("invalid_url", "Webhook url must be http(s)".to_string()))
+    }
+}
+
+/// A single failed rule, reported in `ApiResponse::errors`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FieldError {
+    /// The offending request field, or null for rules spanning several fields.
+    pub field: Option<String>,
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub message: String,
+    /// Stable machine-readable error code, see `ApiError::code`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Every failed validation rule, present on `validation_failed` responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
 }
 
 impl<T> ApiResponse<T> {
@@ -137,14 +1373,23 @@ impl<T> ApiResponse<T> {
             success: true,
             data: Some(data),
             message: "Success".to_string(),
+            code: None,
+            errors: None,
         }
     }
     
-    pub fn error(message: String) -> Self {
+    pub fn error(code: &str, message: String) -> Self {
         Self {
             success: false,
             data: None,
             message,
+            code: Some(code.to_string()),
+            errors: None,
         }
     }
+    
+    pub fn with_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = Some(errors);
+        self
+    }
 }
\ No newline at end of file
@@ -0,0 +1,157 @@
+use utoipa::{OpenApi, ToSchema};
+
+use crate::handlers;
+use crate::models::{FieldError, QrFormat};
+
+/// Documents the body `ApiError` renders, i.e. an `ApiResponse<()>` with a
+/// `code` and, for `validation_failed`, the individual field `errors`.
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct ErrorResponse {
+    success: bool,
+    data: Option<serde_json::Value>,
+    message: String,
+    code: String,
+    errors: Option<Vec<FieldError>>,
+}
+
+/// OpenAPI document for the `/api/v2` routes, assembled from the `#[utoipa::path]`
+/// annotations in `handlers.rs` and the `ToSchema` derives in `models.rs`.
+/// A snapshot is checked in at `openapi.json`; see the tests below.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Verichain API", description = "Cold-chain product registry, escrowed shipments and custody tracking."),
+    servers((url = "/api/v2")),
+    paths(
+        handlers::get_products,
+        handlers::register_product,
+        handlers::get_product,
+        handlers::get_product_lots,
+        handlers::trace_product_lot,
+        handlers::update_product,
+        handlers::delete_product,
+        handlers::get_shipments,
+        handlers::fund_escrow,
+        handlers::stream_shipment_updates,
+        handlers::get_shipment,
+        handlers::add_event,
+        handlers::assign_logistics_partner,
+        handlers::acknowledge_handoff,
+        handlers::cancel_shipment,
+        handlers::return_shipment,
+        handlers::confirm_delivery,
+        handlers::ingest_telemetry,
+        handlers::get_telemetry,
+        handlers::get_thermal_summary,
+        handlers::get_shipment_qr,
+        handlers::get_shipment_gs1,
+        handlers::export_epcis,
+        handlers::import_epcis,
+        handlers::parse_gs1,
+        handlers::verify_shipment,
+        handlers::get_shipment_certificate,
+        handlers::get_certificate_key,
+        handlers::create_recall,
+        handlers::get_recalls,
+        handlers::get_recall,
+        handlers::close_recall,
+        handlers::get_users,
+        handlers::get_user,
+        handlers::get_user_shipments,
+        handlers::get_user_devices,
+        handlers::get_user_webhooks,
+        handlers::create_webhook,
+        handlers::delete_webhook,
+        handlers::get_webhook_deliveries,
+        handlers::redeliver_webhook,
+        handlers::register_device,
+        handlers::rotate_device_key,
+        handlers::revoke_device,
+        handlers::get_weather,
+        handlers::verify_password,
+    ),
+    // Only referenced from query parameters, which utoipa doesn't collect
+    components(schemas(QrFormat)),
+    tags(
+        (name = "Products", description = "Product registration and environmental limits"),
+        (name = "Shipments", description = "Escrowed shipments and custody events"),
+        (name = "Telemetry", description = "Sensor readings and thermal exposure"),
+        (name = "Recalls", description = "Product and lot recalls across shipments"),
+        (name = "Users", description = "Supply chain participants"),
+        (name = "Devices", description = "Logistics device API keys"),
+        (name = "Webhooks", description = "Signed outbound event notifications"),
+        (name = "EPCIS", description = "EPCIS 2.0 traceability event exchange"),
+        (name = "GS1", description = "GTINs, lots, serials, Digital Link URIs and GS1-128 element strings"),
+        (name = "Verification", description = "Public checks for scanned QR codes"),
+        (name = "Weather", description = "Ambient temperature lookups"),
+        (name = "Auth", description = "Action passwords"),
+    )
+)]
+pub struct ApiDoc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test as actix_test, web, App};
+    use std::sync::Arc;
+
+    use crate::storage::Storage;
+    use crate::versions::V2;
+
+    const SNAPSHOT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    /// Fails when models or handler annotations change without the checked-in
+    /// spec being regenerated. Run with `UPDATE_SNAPSHOTS=1` to rewrite it.
+    #[test]
+    fn spec_matches_snapshot() {
+        let generated = ApiDoc::openapi().to_pretty_json().expect("spec serializes") + "\n";
+        
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(SNAPSHOT_PATH, &generated).expect("snapshot is writable");
+            return;
+        }
+        
+        let snapshot = std::fs::read_to_string(SNAPSHOT_PATH).unwrap_or_default();
+        assert!(
+            snapshot == generated,
+            "openapi.json is out of date, regenerate it with `UPDATE_SNAPSHOTS=1 cargo test`"
+        );
+    }
+
+    /// Fails when a documented operation has no matching actix route.
+    #[actix_web::test]
+    async fn documented_operations_are_routed() {
+        let storage = Arc::new(Storage::new());
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(storage))
+                .service(web::scope("/api/v2").configure(crate::api_routes::<V2>)),
+        )
+        .await;
+        
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            let uri = format!("/api/v2{}", path.replace("{id}", "probe").replace("{location}", "probe").replace("{token}", "probe").replace("{lot}", "probe"));
+            let operations = [
+                ("GET", &item.get),
+                ("POST", &item.post),
+                ("PUT", &item.put),
+                ("PATCH", &item.patch),
+                ("DELETE", &item.delete),
+            ];
+            for (method, _) in operations.iter().filter(|(_, operation)| operation.is_some()) {
+                let req = actix_test::TestRequest::default()
+                    .method(method.parse().unwrap())
+                    .uri(&uri)
+                    .to_request();
+                let res = actix_test::call_service(&app, req).await;
+                
+                // Unmatched routes answer with an empty 404 or 405, handler errors carry a body
+                assert_ne!(res.status(), StatusCode::METHOD_NOT_ALLOWED, "{} {} is not routed", method, path);
+                if res.status() == StatusCode::NOT_FOUND {
+                    let body = actix_test::read_body(res).await;
+                    assert!(!body.is_empty(), "{} {} is not routed", method, path);
+                }
+            }
+        }
+    }
+}
@@ -1,20 +1,42 @@
 use reqwest;
 use serde_json::Value;
 use anyhow::Result;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use aes_gcm_siv::{aead::Aead, Aes256GcmSiv, Nonce};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hmac::{Hmac, Mac};
+use image::{ImageFormat, Luma};
+use qrcode::{render::svg, EcLevel, QrCode};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
 
-use crate::models::WeatherResponse;
+use crate::storage::Storage;
+use crate::errors::{ApiError, CertificateError};
+use crate::models::{
+    ConstraintViolation, DeliveryAttempt, DeliveryStatus, ShipmentUpdate, WebhookDelivery, WebhookEventType, EnvironmentalMetric, Product, TelemetryExcursion, TelemetryReading, ThermalSummary,
+    ViolationKind, WeatherResponse, QrFormat, Shipment, ShipmentEvent, ShipmentStatus, User, VerificationSummary, VerifiedEvent,
+    VerifiedItem, CertificateClaims, LineItem, Money, CertificateKey, ShipmentCertificate, LotShipment, LotSummary, LotTrace,
+    Recall, RecallReport, RecalledShipment, ReturnDetails, ReturnReason,
+};
+use crate::versions::ShipmentUpdateV1;
 
 pub struct WeatherService;
 
 impl WeatherService {
     const API_KEY: &'static str = "e795de305b55520d3c3f83fb25e79673";
-    const BASE_URL: &'static str = "https://api.openweathermap.org/data/2.5/weather";
     
-    pub async fn get_temperature(location: &str) -> Result<f64> {
+    /// Looks up the current temperature from the OpenWeather-compatible API at
+    /// `base_url`, see `Storage::weather_url`.
+    pub async fn get_temperature(base_url: &str, location: &str) -> Result<f64> {
         let url = format!(
             "{}?q={},IN&appid={}&units=metric",
-            Self::BASE_URL,
+            base_url,
             location,
             Self::API_KEY
         );
@@ -40,10 +62,10 @@ impl WeatherService {
         }
     }
     
-    pub async fn verify_temperature(location: &str, _reported_temp: f64) -> Result<WeatherResponse> {
+    pub async fn verify_temperature(base_url: &str, location: &str, _reported_temp: f64) -> Result<WeatherResponse> {
         let url = format!(
             "{}?q={},IN&appid={}&units=metric",
-            Self::BASE_URL,
+            base_url,
             location,
             Self::API_KEY
         );
@@ -127,4 +149,1096 @@ impl PasswordService {
             _ => false,
         }
     }
-}
\ No newline at end of file
+}
+
+fn random_token(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+pub struct DeviceKeyService;
+
+impl DeviceKeyService {
+    const KEY_PREFIX: &'static str = "vck";
+    
+    /// Generates a fresh device API key, returning `(api_key, key_prefix, key_hash)`.
+    /// Only the hash is persisted; the prefix lets operators tell keys apart.
+    pub fn generate_key() -> (String, String, String) {
+        let api_key = format!("{}_{}", Self::KEY_PREFIX, random_token(40));
+        let key_prefix = api_key[..Self::KEY_PREFIX.len() + 9].to_string();
+        let key_hash = Self::hash_key(&api_key);
+        
+        (api_key, key_prefix, key_hash)
+    }
+    
+    pub fn hash_key(api_key: &str) -> String {
+        hex::encode(Sha256::digest(api_key.as_bytes()))
+    }
+}
+
+pub struct TelemetryService;
+
+impl TelemetryService {
+    /// Checks logger readings against the product's limits. Unlike milestone
+    /// events this never calls the weather API; the logger is the source of truth.
+    pub fn evaluate(product: &Product, readings: &[TelemetryReading]) -> Vec<TelemetryExcursion> {
+        readings
+            .iter()
+            .filter_map(|reading| {
+                let violations = Self::check_reading(product, reading);
+                if violations.is_empty() {
+                    None
+                } else {
+                    Some(TelemetryExcursion {
+                        timestamp: reading.timestamp,
+                        violations,
+                    })
+                }
+            })
+            .collect()
+    }
+    
+    /// Returns every constraint of `product` that `reading` violates. Channels
+    /// missing from the reading are not checked.
+    pub fn check_reading(product: &Product, reading: &TelemetryReading) -> Vec<ConstraintViolation> {
+        product
+            .all_constraints()
+            .into_iter()
+            .filter_map(|constraint| {
+                let value = reading.value(constraint.metric)?;
+                match (constraint.min, constraint.max) {
+                    (Some(min), _) if value < min => Some(ConstraintViolation {
+                        metric: constraint.metric,
+                        kind: ViolationKind::BelowMin,
+                        value,
+                        limit: min,
+                    }),
+                    (_, Some(max)) if value > max => Some(ConstraintViolation {
+                        metric: constraint.metric,
+                        kind: ViolationKind::AboveMax,
+                        value,
+                        limit: max,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+    
+    /// When the first non-temperature violation happened. Those compromise a
+    /// shipment immediately; temperature excursions are judged against the
+    /// excursion budget instead.
+    pub fn first_immediate_violation(excursions: &[TelemetryExcursion]) -> Option<DateTime<Utc>> {
+        excursions
+            .iter()
+            .filter(|excursion| excursion.violations.iter().any(|violation| violation.metric != EnvironmentalMetric::Temperature))
+            .map(|excursion| excursion.timestamp)
+            .min()
+    }
+}
+
+pub struct ThermalService;
+
+impl ThermalService {
+    /// Activation energy over the gas constant (ΔH/R) in kelvin, using the
+    /// USP <1079> default of 83.144 kJ/mol.
+    const ACTIVATION_ENERGY_OVER_R: f64 = 83.144 / 0.0083144;
+    const KELVIN_OFFSET: f64 = 273.15;
+    
+    /// Summarises the temperature history of a shipment. Each reading is held
+    /// until the next one, so durations are attributed to the earlier sample.
+    pub fn summarize(shipment_id: &str, product: &Product, readings: &[TelemetryReading]) -> ThermalSummary {
+        let mut samples: Vec<(DateTime<Utc>, f64)> = readings
+            .iter()
+            .filter_map(|reading| reading.temperature.map(|t| (reading.timestamp, t)))
+            .collect();
+        samples.sort_by_key(|(timestamp, _)| *timestamp);
+        
+        let budget = product.excursion_budget_minutes;
+        let mut minutes_above_max = 0.0;
+        let mut minutes_below_min = 0.0;
+        let mut budget_exceeded_at = None;
+        let mut weighted = Vec::with_capacity(samples.len());
+        
+        for (i, (timestamp, temperature)) in samples.iter().enumerate() {
+            let minutes = samples
+                .get(i + 1)
+                .map(|(next, _)| (*next - *timestamp).num_milliseconds() as f64 / 60_000.0)
+                .unwrap_or(0.0);
+            
+            weighted.push((*temperature, minutes));
+            let spent = minutes_above_max + minutes_below_min;
+            if *temperature > product.max_temperature {
+                minutes_above_max += minutes;
+            } else if *temperature < product.min_temperature {
+                minutes_below_min += minutes;
+            } else {
+                continue;
+            }
+            
+            // Without a budget the first excursion spends it; otherwise it runs out part way through this one
+            if budget_exceeded_at.is_none() && (budget <= 0.0 || spent + minutes > budget) {
+                let into_excursion = (budget - spent).max(0.0);
+                budget_exceeded_at = Some(*timestamp + chrono::Duration::milliseconds((into_excursion * 60_000.0) as i64));
+            }
+        }
+        
+        ThermalSummary {
+            shipment_id: shipment_id.to_string(),
+            reading_count: samples.len(),
+            first_reading_at: samples.first().map(|(timestamp, _)| *timestamp),
+            last_reading_at: samples.last().map(|(timestamp, _)| *timestamp),
+            mean_kinetic_temperature: Self::mean_kinetic_temperature(&weighted),
+            min_temperature: samples.iter().map(|(_, t)| *t).reduce(f64::min),
+            max_temperature: samples.iter().map(|(_, t)| *t).reduce(f64::max),
+            minutes_above_max,
+            minutes_below_min,
+            excursion_budget_minutes: budget,
+            budget_exceeded: budget_exceeded_at.is_some(),
+            budget_exceeded_at,
+        }
+    }
+    
+    /// Time-weighted mean kinetic temperature in °C from `(celsius, minutes)`
+    /// pairs. Falls back to equal weights when the readings span no time.
+    fn mean_kinetic_temperature(samples: &[(f64, f64)]) -> Option<f64> {
+        if samples.is_empty() {
+            return None;
+        }
+        
+        let total_minutes: f64 = samples.iter().map(|(_, minutes)| minutes).sum();
+        let weight = |minutes: f64| if total_minutes > 0.0 { minutes } else { 1.0 };
+        let total_weight: f64 = samples.iter().map(|(_, minutes)| weight(*minutes)).sum();
+        
+        let exponential_sum: f64 = samples
+            .iter()
+            .map(|(celsius, minutes)| {
+                let kelvin = celsius + Self::KELVIN_OFFSET;
+                weight(*minutes) * (-Self::ACTIVATION_ENERGY_OVER_R / kelvin).exp()
+            })
+            .sum();
+        
+        let kelvin = Self::ACTIVATION_ENERGY_OVER_R / -(exponential_sum / total_weight).ln();
+        Some(((kelvin - Self::KELVIN_OFFSET) * 100.0).round() / 100.0)
+    }
+}
+
+pub struct WebhookService;
+
+impl WebhookService {
+    const MAX_RETRIES: u32 = 8;
+    const BASE_BACKOFF_SECS: i64 = 5;
+    const MAX_BACKOFF_SECS: i64 = 3600;
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+    
+    pub fn generate_secret() -> String {
+        format!("whsec_{}", random_token(32))
+    }
+    
+    /// Signs `{timestamp}.{body}` with HMAC-SHA256, formatted for the
+    /// `X-Verichain-Signature` header as `t=<timestamp>,v1=<hex digest>`.
+    pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+    }
+    
+    /// Runs the webhook pipeline: queues a delivery per matching subscription
+    /// for every shipment update, and drains due deliveries with backoff.
+    pub async fn run(storage: Arc<Storage>) {
+        actix_web::rt::spawn(Self::enqueue_updates(storage.clone()));
+        
+        let client = reqwest::Client::builder()
+            .timeout(Self::REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        let busy = Arc::new(Mutex::new(HashSet::new()));
+        let mut interval = tokio::time::interval(Self::POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            Self::dispatch_due(&storage, &client, &busy);
+        }
+    }
+    
+    /// Sends each subscription's due deliveries in order on a task of its own,
+    /// so a slow receiver only holds up its own deliveries. Subscriptions in
+    /// `busy` still have a task draining them and are picked up on a later tick.
+    fn dispatch_due(storage: &Arc<Storage>, client: &reqwest::Client, busy: &Arc<Mutex<HashSet<String>>>) {
+        let mut due: HashMap<String, Vec<WebhookDelivery>> = HashMap::new();
+        for delivery in storage.get_due_webhook_deliveries() {
+            due.entry(delivery.subscription_id.clone()).or_default().push(delivery);
+        }
+        
+        for (subscription_id, deliveries) in due {
+            if !busy.lock().unwrap().insert(subscription_id.clone()) {
+                continue;
+            }
+            let (storage, client, busy) = (storage.clone(), client.clone(), busy.clone());
+            actix_web::rt::spawn(async move {
+                for delivery in deliveries {
+                    Self::attempt(&storage, &client, delivery).await;
+                }
+                busy.lock().unwrap().remove(&subscription_id);
+            });
+        }
+    }
+    
+    async fn enqueue_updates(storage: Arc<Storage>) {
+        let mut receiver = storage.subscribe();
+        loop {
+            match receiver.recv().await {
+                Ok(update) => Self::enqueue(&storage, &update),
+                Err(RecvError::Lagged(skipped)) => log::warn!("Webhook queue skipped {} shipment updates", skipped),
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+    
+    fn enqueue(storage: &Storage, update: &ShipmentUpdate) {
+        let event_type = WebhookEventType::from_change(&update.change);
+        
+        for subscription in storage.webhooks.iter().map(|entry| entry.value().clone()) {
+            if !subscription.events.contains(&event_type) {
+                continue;
+            }
+            let involved = storage
+                .get_user(&subscription.user_id)
+                .is_some_and(|user| update.shipment.involves(&user.id, user.role.as_str()));
+            if !involved {
+                continue;
+            }
+            
+            let id = format!("whd-{}", Uuid::new_v4());
+            let payload = serde_json::json!({
+                "id": id,
+                "type": event_type.as_str(),
+                "created_at": update.timestamp,
+                // Webhook consumers keep receiving the v1 shapes
+                "data": ShipmentUpdateV1::from(update.clone()),
+            });
+            storage.upsert_webhook_delivery(WebhookDelivery {
+                id,
+                subscription_id: subscription.id,
+                event_type,
+                payload,
+                status: DeliveryStatus::Pending,
+                retries: 0,
+                next_attempt_at: Some(Utc::now()),
+                attempts: vec![],
+                created_at: Utc::now(),
+            });
+        }
+    }
+    
+    async fn attempt(storage: &Storage, client: &reqwest::Client, mut delivery: WebhookDelivery) {
+        let subscription = match storage.get_webhook(&delivery.subscription_id) {
+            Some(subscription) => subscription,
+            None => {
+                // The subscription was deleted; nothing left to deliver to
+                delivery.status = DeliveryStatus::Failed;
+                delivery.next_attempt_at = None;
+                storage.upsert_webhook_delivery(delivery);
+                return;
+            }
+        };
+        
+        let body = serde_json::to_vec(&delivery.payload).unwrap_or_default();
+        let signature = Self::sign(&subscription.secret, Utc::now().timestamp(), &body);
+        
+        let started = Instant::now();
+        let attempted_at = Utc::now();
+        let result = client
+            .post(&subscription.url)
+            .header("Content-Type", "application/json")
+            .header("X-Verichain-Event", delivery.event_type.as_str())
+            .header("X-Verichain-Delivery", delivery.id.as_str())
+            .header("X-Verichain-Signature", signature)
+            .body(body)
+            .send()
+            .await;
+        let duration_ms = started.elapsed().as_millis() as u64;
+        
+        let (status_code, error) = match result {
+            Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+            Ok(response) => (Some(response.status().as_u16()), Some(format!("Receiver returned {}", response.status()))),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        
+        delivery.retries += 1;
+        delivery.attempts.push(DeliveryAttempt {
+            attempted_at,
+            status_code,
+            error: error.clone(),
+            duration_ms,
+        });
+        
+        if error.is_none() {
+            delivery.status = DeliveryStatus::Delivered;
+            delivery.next_attempt_at = None;
+        } else if delivery.retries >= Self::MAX_RETRIES {
+            log::warn!("Webhook delivery {} failed after {} attempts", delivery.id, delivery.retries);
+            delivery.status = DeliveryStatus::Failed;
+            delivery.next_attempt_at = None;
+        } else {
+            let backoff = (Self::BASE_BACKOFF_SECS << (delivery.retries - 1)).min(Self::MAX_BACKOFF_SECS);
+            delivery.next_attempt_at = Some(Utc::now() + chrono::Duration::seconds(backoff));
+        }
+        
+        storage.upsert_webhook_delivery(delivery);
+    }
+}
+
+pub struct EscrowService;
+
+impl EscrowService {
+    /// Share of the escrow, in basis points, kept by the manufacturer when
+    /// sound goods come back because the consumer refused them.
+    const RESTOCKING_FEE_BPS: i64 = 1_000;
+    
+    /// What the consumer gets back for a completed return: everything, unless
+    /// they refused goods that were sound and recall-free when sent back, in
+    /// which case the restocking fee is withheld. Damage on the way back is
+    /// the return carrier's to answer for, not the consumer's.
+    pub fn return_refund(shipment: &Shipment, details: &ReturnDetails) -> Money {
+        let consumer_at_fault = details.reason == ReturnReason::Refused
+            && details.returned_from != ShipmentStatus::Compromised
+            && shipment.recalls.is_empty();
+        // Widened so the fee can't overflow; it never exceeds the escrow, so it narrows back
+        let fee = if consumer_at_fault {
+            (i128::from(shipment.escrow.amount_minor) * i128::from(Self::RESTOCKING_FEE_BPS) / 10_000) as i64
+        } else {
+            0
+        };
+        Money {
+            amount_minor: shipment.escrow.amount_minor - fee,
+            currency: shipment.escrow.currency.clone(),
+        }
+    }
+    
+    /// The escrow for a shipment's line items: the total of their prices when
+    /// every item is priced, checked against `requested` if the client sent one.
+    /// Otherwise the client's amount is taken as agreed, as long as it covers
+    /// the items that are priced.
+    pub fn amount_for(line_items: &[LineItem], requested: Option<Money>) -> Result<Money, ApiError> {
+        let too_large = || ApiError::Validation("Line item total is too large".to_string());
+        let mut subtotal: Option<Money> = None;
+        let mut all_priced = true;
+        for item in line_items {
+            let Some(price) = &item.unit_price else {
+                all_priced = false;
+                continue;
+            };
+            let total = price.checked_times(item.quantity).ok_or_else(too_large)?;
+            subtotal = Some(match subtotal {
+                None => total,
+                Some(subtotal) if subtotal.currency != total.currency => {
+                    return Err(ApiError::Validation("Line items are priced in different currencies".to_string()));
+                }
+                Some(subtotal) => Money {
+                    amount_minor: subtotal.amount_minor.checked_add(total.amount_minor).ok_or_else(too_large)?,
+                    currency: subtotal.currency,
+                },
+            });
+        }
+        
+        match (subtotal, requested) {
+            (Some(total), Some(requested)) if all_priced && total != requested => Err(ApiError::Validation(format!(
+                "Escrow of {} {} does not match the line item total of {} {}",
+                requested.amount_minor, requested.currency, total.amount_minor, total.currency
+            ))),
+            (Some(total), _) if all_priced => Ok(total),
+            (Some(subtotal), Some(requested))
+                if requested.currency != subtotal.currency || requested.amount_minor < subtotal.amount_minor =>
+            {
+                Err(ApiError::Validation(format!(
+                    "Escrow of {} {} does not cover the priced items' subtotal of {} {}",
+                    requested.amount_minor, requested.currency, subtotal.amount_minor, subtotal.currency
+                )))
+            }
+            (_, Some(requested)) => Ok(requested),
+            (_, None) => Err(ApiError::Validation("Escrow is required unless every line item is priced".to_string())),
+        }
+    }
+}
+
+/// Answers which shipments carried which lots of a product, e.g. to find
+/// where a bad batch ended up.
+pub struct TraceabilityService;
+
+impl TraceabilityService {
+    /// The lots of `product_id` across `shipments`, in the order first shipped.
+    pub fn lots(product_id: &str, shipments: &[Shipment]) -> Vec<LotSummary> {
+        let mut lots: Vec<LotSummary> = Vec::new();
+        for shipment in shipments {
+            for (lot, item) in Self::lot_items(product_id, shipment) {
+                match lots.iter_mut().find(|summary| summary.lot == lot) {
+                    Some(summary) => {
+                        summary.quantity += item.quantity;
+                        summary.shipment_count += 1;
+                    }
+                    None => lots.push(LotSummary {
+                        lot: lot.to_string(),
+                        expiry: item.expiry,
+                        quantity: item.quantity,
+                        unit: item.unit,
+                        shipment_count: 1,
+                    }),
+                }
+            }
+        }
+        lots
+    }
+    
+    /// Every shipment carrying `lot` of `product_id`, or `None` if none has.
+    pub fn trace(product_id: &str, lot: &str, shipments: &[Shipment]) -> Option<LotTrace> {
+        let mut trace: Option<LotTrace> = None;
+        for shipment in shipments {
+            for item in Self::lot_items(product_id, shipment).filter(|(item_lot, _)| *item_lot == lot).map(|(_, item)| item) {
+                let trace = trace.get_or_insert_with(|| LotTrace {
+                    product_id: product_id.to_string(),
+                    lot: lot.to_string(),
+                    expiry: item.expiry,
+                    quantity: 0.0,
+                    unit: item.unit,
+                    shipments: Vec::new(),
+                });
+                trace.quantity += item.quantity;
+                trace.shipments.push(LotShipment {
+                    shipment_id: shipment.id.clone(),
+                    status: shipment.status.clone(),
+                    quantity: item.quantity,
+                    serials: item.serials.clone(),
+                    manufacturer: shipment.manufacturer.clone(),
+                    logistics_partner: shipment.logistics_partner.clone(),
+                    consumer: shipment.consumer.clone(),
+                    created_at: shipment.created_at,
+                    delivered_at: shipment.delivered_at,
+                });
+            }
+        }
+        trace
+    }
+    
+    fn lot_items<'a>(product_id: &'a str, shipment: &'a Shipment) -> impl Iterator<Item = (&'a str, &'a LineItem)> {
+        shipment
+            .line_items
+            .iter()
+            .filter(move |item| item.product_id == product_id)
+            .filter_map(|item| item.lot.as_deref().map(|lot| (lot, item)))
+    }
+}
+
+pub struct RecallService;
+
+impl RecallService {
+    /// Where the recalled goods in each of `shipments` are now. Shipments
+    /// without covered items are left out.
+    pub fn report(recall: Recall, shipments: &[Shipment]) -> RecallReport {
+        let shipments = shipments
+            .iter()
+            .filter_map(|shipment| {
+                let items: Vec<&LineItem> = recall.covered_items(shipment).collect();
+                let first = items.first()?;
+                let mut lots: Vec<String> = Vec::new();
+                for lot in items.iter().filter_map(|item| item.lot.clone()) {
+                    if !lots.contains(&lot) {
+                        lots.push(lot);
+                    }
+                }
+                Some(RecalledShipment {
+                    shipment_id: shipment.id.clone(),
+                    status: shipment.status.clone(),
+                    custodian: shipment.custodian().to_string(),
+                    last_location: shipment.events.last().map(|event| event.location.clone()),
+                    consumer: shipment.consumer.clone(),
+                    lots,
+                    serials: items.iter().flat_map(|item| item.serials.iter().cloned()).collect(),
+                    quantity: items.iter().map(|item| item.quantity).sum(),
+                    unit: first.unit,
+                    escrow_frozen: !shipment.escrow_released && shipment.escrow_refund.is_none() && !shipment.recalls.is_empty(),
+                })
+            })
+            .collect();
+        RecallReport { recall, shipments }
+    }
+}
+
+/// Issues and checks the opaque tokens printed on shipment QR codes. A token
+/// is the shipment id sealed with AES-256-GCM-SIV under a key derived from the
+/// verification key, behind a random nonce. Tokens reveal nothing about the
+/// shipment and can't be forged.
+pub struct VerificationService;
+
+impl VerificationService {
+    const NONCE_BYTES: usize = 12;
+    
+    fn cipher(key: &[u8]) -> Aes256GcmSiv {
+        let mut hasher = Sha256::new();
+        hasher.update(b"verichain-verify:");
+        hasher.update(key);
+        // Not imported, as `hmac::Mac` has a `new_from_slice` of its own
+        <Aes256GcmSiv as aes_gcm_siv::KeyInit>::new(&hasher.finalize())
+    }
+    
+    pub fn issue_token(key: &[u8], shipment_id: &str) -> String {
+        let nonce = rand::random::<[u8; Self::NONCE_BYTES]>();
+        let sealed = Self::cipher(key)
+            .encrypt(Nonce::from_slice(&nonce), shipment_id.as_bytes())
+            .expect("shipment ids are far below the AEAD's length limit");
+        URL_SAFE_NO_PAD.encode([&nonce[..], &sealed].concat())
+    }
+    
+    /// Returns the shipment id of a token issued with `key`.
+    pub fn verify_token(key: &[u8], token: &str) -> Option<String> {
+        let token = URL_SAFE_NO_PAD.decode(token).ok()?;
+        if token.len() <= Self::NONCE_BYTES {
+            return None;
+        }
+        let (nonce, sealed) = token.split_at(Self::NONCE_BYTES);
+        let shipment_id = Self::cipher(key).decrypt(Nonce::from_slice(nonce), sealed).ok()?;
+        String::from_utf8(shipment_id).ok()
+    }
+    
+    /// The public view of a shipment: no escrow, parties by name and no internal ids.
+    pub fn summarize(shipment: &Shipment, manufacturer: Option<&User>) -> VerificationSummary {
+        let cold_chain_intact = shipment.compromised_at.is_none()
+            && shipment.events.iter().all(|event| event.violations.is_empty());
+        
+        VerificationSummary {
+            items: shipment
+                .line_items
+                .iter()
+                .map(|item| VerifiedItem {
+                    gtin: item.product.gtin.clone(),
+                    name: item.product.name.clone(),
+                    description: item.product.description.clone(),
+                    lot: item.lot.clone(),
+                    expiry: item.expiry,
+                    quantity: item.quantity,
+                    unit: item.unit,
+                    min_temperature: item.product.min_temperature,
+                    max_temperature: item.product.max_temperature,
+                })
+                .collect(),
+            manufacturer: manufacturer.map(|user| user.name.clone()),
+            status: shipment.status.clone(),
+            cold_chain_intact,
+            record_intact: EventChainService::verify(shipment),
+            shipped_at: shipment.created_at,
+            delivered_at: shipment.delivered_at,
+            journey: shipment
+                .events
+                .iter()
+                .map(|event| VerifiedEvent {
+                    event_type: event.event_type.clone(),
+                    location: event.location.clone(),
+                    timestamp: event.timestamp,
+                    within_limits: event.violations.is_empty(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Issues shipment certificates as compact JWS signed with EdDSA/Ed25519, and
+/// verifies them without contacting the server.
+pub struct CertificateService;
+
+impl CertificateService {
+    pub const ALGORITHM: &'static str = "EdDSA";
+    const TYPE: &'static str = "verichain-certificate+jws";
+    
+    /// Short, stable id for a public key: the first 8 bytes of its SHA-256, in hex.
+    pub fn key_id(key: &VerifyingKey) -> String {
+        hex::encode(&Sha256::digest(key.as_bytes())[..8])
+    }
+    
+    pub fn public_key(signing_key: &SigningKey) -> CertificateKey {
+        let key = signing_key.verifying_key();
+        CertificateKey {
+            kid: Self::key_id(&key),
+            algorithm: Self::ALGORITHM.to_string(),
+            public_key: URL_SAFE_NO_PAD.encode(key.as_bytes()),
+        }
+    }
+    
+    /// Parses a public key as published by `public_key`.
+    pub fn parse_public_key(encoded: &str) -> Option<VerifyingKey> {
+        let bytes: [u8; 32] = URL_SAFE_NO_PAD.decode(encoded.trim()).ok()?.try_into().ok()?;
+        VerifyingKey::from_bytes(&bytes).ok()
+    }
+    
+    pub fn issue(signing_key: &SigningKey, shipment: &Shipment, manufacturer: Option<&User>) -> ShipmentCertificate {
+        let claims = CertificateClaims {
+            issuer: "verichain".to_string(),
+            issued_at: Utc::now(),
+            summary: VerificationService::summarize(shipment, manufacturer),
+            chain_head: EventChainService::head(shipment),
+            event_count: shipment.events.len(),
+        };
+        
+        let header = serde_json::json!({
+            "alg": Self::ALGORITHM,
+            "typ": Self::TYPE,
+            "kid": Self::key_id(&signing_key.verifying_key()),
+        });
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).expect("claims serialize")),
+        );
+        let signature = signing_key.sign(signing_input.as_bytes());
+        
+        ShipmentCertificate {
+            certificate: format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.to_bytes())),
+            claims,
+        }
+    }
+    
+    /// Checks a certificate against a trusted public key and returns its claims.
+    /// Needs no network access or server state.
+    pub fn verify(certificate: &str, public_key: &VerifyingKey) -> Result<CertificateClaims, CertificateError> {
+        let (signing_input, signature) = certificate.trim().rsplit_once('.').ok_or(CertificateError::Malformed)?;
+        let (header, payload) = signing_input.split_once('.').ok_or(CertificateError::Malformed)?;
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| CertificateError::Malformed);
+        
+        let header: Value = serde_json::from_slice(&decode(header)?).map_err(|_| CertificateError::Malformed)?;
+        let algorithm = header["alg"].as_str().unwrap_or_default();
+        if algorithm != Self::ALGORITHM {
+            return Err(CertificateError::UnsupportedAlgorithm(algorithm.to_string()));
+        }
+        let kid = header["kid"].as_str().unwrap_or_default();
+        if kid != Self::key_id(public_key) {
+            return Err(CertificateError::UnknownKey(kid.to_string()));
+        }
+        
+        let signature = Signature::from_slice(&decode(signature)?).map_err(|_| CertificateError::Malformed)?;
+        public_key
+            .verify_strict(signing_input.as_bytes(), &signature)
+            .map_err(|_| CertificateError::BadSignature)?;
+        
+        serde_json::from_slice(&decode(payload)?).map_err(|_| CertificateError::Malformed)
+    }
+}
+
+/// Links a shipment's events into a hash chain. Each event's `hash` covers the
+/// previous hash and the event itself, starting from a hash of the shipment's
+/// fixed details, so editing, dropping or reordering events breaks the chain.
+pub struct EventChainService;
+
+impl EventChainService {
+    fn genesis(shipment: &Shipment) -> String {
+        let fixed = serde_json::json!({
+            "id": shipment.id,
+            "line_items": shipment.line_items,
+            "manufacturer": shipment.manufacturer,
+            "consumer": shipment.consumer,
+            "created_at": shipment.created_at,
+        });
+        hex::encode(Sha256::digest(fixed.to_string()))
+    }
+    
+    fn link(previous: &str, event: &ShipmentEvent) -> String {
+        let unsealed = ShipmentEvent { hash: String::new(), ..event.clone() };
+        let mut hasher = Sha256::new();
+        hasher.update(previous);
+        hasher.update(serde_json::to_vec(&unsealed).expect("events serialize"));
+        hex::encode(hasher.finalize())
+    }
+    
+    /// Hash of the latest event, or of the shipment itself before any events.
+    pub fn head(shipment: &Shipment) -> String {
+        match shipment.events.last() {
+            Some(event) => event.hash.clone(),
+            None => Self::genesis(shipment),
+        }
+    }
+    
+    /// Seals `event` onto the end of the shipment's chain.
+    pub fn append(shipment: &mut Shipment, mut event: ShipmentEvent) {
+        event.hash = Self::link(&Self::head(shipment), &event);
+        shipment.events.push(event);
+    }
+    
+    /// Recomputes the chain and checks it against the stored hashes.
+    pub fn verify(shipment: &Shipment) -> bool {
+        let mut previous = Self::genesis(shipment);
+        for event in &shipment.events {
+            if Self::link(&previous, event) != event.hash {
+                return false;
+            }
+            previous = event.hash.clone();
+        }
+        true
+    }
+}
+
+pub struct QrService;
+
+impl QrService {
+    pub const DEFAULT_SIZE: u32 = 256;
+    
+    /// Renders `data` as a QR code image, returning the bytes and content type.
+    pub fn render(data: &str, format: QrFormat, size: u32) -> Result<(Vec<u8>, &'static str), ApiError> {
+        let code = QrCode::with_error_correction_level(data, EcLevel::M)
+            .map_err(|e| ApiError::Validation(format!("Cannot encode QR code: {}", e)))?;
+        
+        match format {
+            QrFormat::Svg => {
+                let image = code.render::<svg::Color>().min_dimensions(size, size).build();
+                Ok((image.into_bytes(), "image/svg+xml"))
+            }
+            QrFormat::Png => {
+                let image = code.render::<Luma<u8>>().min_dimensions(size, size).build();
+                let mut bytes = std::io::Cursor::new(Vec::new());
+                image
+                    .write_to(&mut bytes, ImageFormat::Png)
+                    .map_err(|e| ApiError::Validation(format!("Cannot render QR code: {}", e)))?;
+                Ok((bytes.into_inner(), "image/png"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{EventType, ShipmentChange, WebhookSubscription};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    async fn shipment_with_event() -> Shipment {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        let mut shipment = storage.get_shipment("ship-001").expect("mock shipment");
+        EventChainService::append(&mut shipment, ShipmentEvent {
+            id: "event-1".to_string(),
+            timestamp: Utc::now(),
+            location: "Mumbai".to_string(),
+            temperature: 5.0,
+            verified_temperature: 5.0,
+            reporter: "logi-001".to_string(),
+            event_type: EventType::Pickup,
+            is_temperature_valid: true,
+            humidity: None,
+            shock: None,
+            light: None,
+            violations: vec![],
+            coordinates: None,
+            hash: String::new(),
+        });
+        shipment
+    }
+
+    #[actix_web::test]
+    async fn event_chain_detects_edits() {
+        let mut shipment = shipment_with_event().await;
+        assert!(EventChainService::verify(&shipment));
+        
+        shipment.events[0].location = "Pune".to_string();
+        assert!(!EventChainService::verify(&shipment));
+    }
+
+    #[actix_web::test]
+    async fn certificates_verify_offline() {
+        let shipment = shipment_with_event().await;
+        let signing_key = SigningKey::from_bytes(&rand::random());
+        let public_key = CertificateService::parse_public_key(&CertificateService::public_key(&signing_key).public_key)
+            .expect("published key parses");
+        
+        let issued = CertificateService::issue(&signing_key, &shipment, None);
+        let claims = CertificateService::verify(&issued.certificate, &public_key).expect("certificate verifies");
+        assert_eq!(claims.chain_head, EventChainService::head(&shipment));
+        assert_eq!(claims.event_count, 1);
+        
+        // A payload from another certificate doesn't match the signature
+        let (header, rest) = issued.certificate.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+        let other = CertificateService::issue(&signing_key, &Shipment { id: "ship-002".to_string(), ..shipment.clone() }, None);
+        let payload = other.certificate.split('.').nth(1).unwrap();
+        let forged = format!("{}.{}.{}", header, payload, signature);
+        assert!(matches!(CertificateService::verify(&forged, &public_key), Err(CertificateError::BadSignature)));
+        
+        let other_key = SigningKey::from_bytes(&rand::random()).verifying_key();
+        assert!(matches!(CertificateService::verify(&issued.certificate, &other_key), Err(CertificateError::UnknownKey(_))));
+    }
+
+    #[actix_web::test]
+    async fn return_refund_withholds_fee_only_for_refused_sound_goods() {
+        let mut shipment = shipment_with_event().await;
+        let mut details = ReturnDetails {
+            reason: ReturnReason::Refused,
+            details: None,
+            requested_by: "cons-001".to_string(),
+            requested_at: Utc::now(),
+            returned_from: ShipmentStatus::Delivered,
+            returned_at: None,
+            cold_chain_intact: false,
+        };
+        assert_eq!(EscrowService::return_refund(&shipment, &details).amount_minor, 4_500_000);
+        
+        details.returned_from = ShipmentStatus::Compromised;
+        assert_eq!(EscrowService::return_refund(&shipment, &details).amount_minor, 5_000_000);
+        
+        details.returned_from = ShipmentStatus::Delivered;
+        shipment.recalls.push("recall-1".to_string());
+        assert_eq!(EscrowService::return_refund(&shipment, &details).amount_minor, 5_000_000);
+        
+        shipment.recalls.clear();
+        details.reason = ReturnReason::Damaged;
+        assert_eq!(EscrowService::return_refund(&shipment, &details).amount_minor, 5_000_000);
+        
+        details.reason = ReturnReason::Refused;
+        shipment.escrow.amount_minor = i64::MAX;
+        assert_eq!(EscrowService::return_refund(&shipment, &details).amount_minor, i64::MAX - i64::MAX / 10);
+    }
+
+    fn readings(start: DateTime<Utc>, samples: &[(i64, f64)]) -> Vec<TelemetryReading> {
+        samples
+            .iter()
+            .map(|(minutes, temperature)| TelemetryReading {
+                timestamp: start + chrono::Duration::minutes(*minutes),
+                temperature: Some(*temperature),
+                humidity: None,
+                shock: None,
+                light: None,
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn mean_kinetic_temperature_weights_by_duration() {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        let product = storage.get_product("prod-001").expect("mock product");
+        let start = Utc::now();
+        
+        // The last reading spans no time, so each of these is held for half an hour
+        let even = ThermalService::summarize("ship-001", &product, &readings(start, &[(0, 0.0), (30, 20.0), (60, 0.0)]));
+        assert_eq!(even.mean_kinetic_temperature, Some(14.82));
+        
+        let skewed = ThermalService::summarize("ship-001", &product, &readings(start, &[(0, 0.0), (45, 20.0), (60, 0.0)]));
+        assert_eq!(skewed.mean_kinetic_temperature, Some(10.31));
+        
+        let single = ThermalService::summarize("ship-001", &product, &readings(start, &[(0, 4.0)]));
+        assert_eq!(single.mean_kinetic_temperature, Some(4.0));
+    }
+
+    #[actix_web::test]
+    async fn budget_runs_out_part_way_through_an_excursion() {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        let mut product = storage.get_product("prod-001").expect("mock product");
+        product.min_temperature = 2.0;
+        product.max_temperature = 8.0;
+        let start = Utc::now();
+        let history = readings(start, &[(0, 5.0), (10, 12.0), (40, 1.0), (50, 5.0)]);
+        
+        product.excursion_budget_minutes = 35.0;
+        let summary = ThermalService::summarize("ship-001", &product, &history);
+        assert_eq!(summary.minutes_above_max, 30.0);
+        assert_eq!(summary.minutes_below_min, 10.0);
+        assert_eq!(summary.budget_exceeded_at, Some(start + chrono::Duration::minutes(45)));
+        assert!(summary.budget_exceeded);
+        
+        product.excursion_budget_minutes = 40.0;
+        let summary = ThermalService::summarize("ship-001", &product, &history);
+        assert_eq!(summary.budget_exceeded_at, None, "exactly spending the budget is allowed");
+        
+        product.excursion_budget_minutes = 0.0;
+        let summary = ThermalService::summarize("ship-001", &product, &history);
+        assert_eq!(summary.budget_exceeded_at, Some(start + chrono::Duration::minutes(10)));
+    }
+
+    #[actix_web::test]
+    async fn escrow_covers_priced_items_without_overflowing() {
+        let shipment = shipment_with_event().await;
+        let inr = |amount_minor: i64| Money { amount_minor, currency: "INR".to_string() };
+        let item = |unit_price: Option<Money>, quantity: f64| LineItem {
+            unit_price,
+            quantity,
+            ..shipment.line_items[0].clone()
+        };
+        
+        let priced = [item(Some(inr(1_000)), 3.0), item(Some(inr(500)), 2.0)];
+        assert_eq!(EscrowService::amount_for(&priced, None).unwrap(), inr(4_000));
+        assert!(EscrowService::amount_for(&priced, Some(inr(4_001))).is_err());
+        
+        // With an unpriced item the escrow is the client's, but must cover what is priced
+        let partly_priced = [item(Some(inr(1_000)), 3.0), item(None, 2.0)];
+        assert!(matches!(EscrowService::amount_for(&partly_priced, None), Err(ApiError::Validation(_))));
+        assert!(matches!(EscrowService::amount_for(&partly_priced, Some(inr(2_999))), Err(ApiError::Validation(_))));
+        assert_eq!(EscrowService::amount_for(&partly_priced, Some(inr(5_000))).unwrap(), inr(5_000));
+        
+        let huge = [item(Some(inr(i64::MAX / 2)), 3.0)];
+        assert!(matches!(EscrowService::amount_for(&huge, None), Err(ApiError::Validation(_))));
+        let summing_over = [item(Some(inr(i64::MAX / 2)), 1.0), item(Some(inr(i64::MAX / 2)), 1.0), item(Some(inr(2)), 1.0)];
+        assert!(matches!(EscrowService::amount_for(&summing_over, None), Err(ApiError::Validation(_))));
+    }
+
+    #[test]
+    fn verification_tokens_are_opaque_and_unforgeable() {
+        let key = b"verification key";
+        let token = VerificationService::issue_token(key, "ship-001");
+        assert!(!token.contains("ship"), "{token}");
+        assert_eq!(VerificationService::verify_token(key, &token).as_deref(), Some("ship-001"));
+        
+        // Fresh nonces keep tokens for the same shipment unlinkable
+        let again = VerificationService::issue_token(key, "ship-001");
+        assert_ne!(token, again);
+        assert_eq!(VerificationService::verify_token(key, &again).as_deref(), Some("ship-001"));
+        
+        assert_eq!(VerificationService::verify_token(b"another key", &token), None);
+        let mut tampered = URL_SAFE_NO_PAD.decode(&token).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(VerificationService::verify_token(key, &URL_SAFE_NO_PAD.encode(tampered)), None);
+        assert_eq!(VerificationService::verify_token(key, "ship-001"), None);
+    }
+
+    /// Answers one HTTP request per status in turn, returning each request's
+    /// head and body.
+    async fn receive_webhooks(listener: TcpListener, statuses: &[&str]) -> Vec<(String, Vec<u8>)> {
+        let mut requests = Vec::new();
+        for status in statuses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let (head, body_start, content_length) = loop {
+                let mut buffer = [0; 4096];
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                    let content_length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length:"))
+                        .and_then(|length| length.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    break (head, end + 4, content_length);
+                }
+            };
+            while request.len() < body_start + content_length {
+                let mut buffer = [0; 4096];
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+            socket.write_all(response.as_bytes()).await.unwrap();
+            requests.push((head, request[body_start..].to_vec()));
+        }
+        requests
+    }
+
+    #[actix_web::test]
+    async fn webhook_deliveries_are_signed_and_retried_with_backoff() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = actix_web::rt::spawn(receive_webhooks(listener, &["500 Internal Server Error", "503 Service Unavailable", "204 No Content"]));
+        
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        storage.add_webhook(WebhookSubscription {
+            id: "webhook-1".to_string(),
+            user_id: "cons-001".to_string(),
+            url,
+            events: vec![WebhookEventType::ShipmentCreated],
+            secret: "whsec_test".to_string(),
+            created_at: Utc::now(),
+        }).unwrap();
+        let shipment = storage.get_shipment("ship-001").unwrap();
+        WebhookService::enqueue(&storage, &ShipmentUpdate {
+            shipment_id: shipment.id.clone(),
+            timestamp: Utc::now(),
+            change: ShipmentChange::Created,
+            shipment,
+        });
+        let delivery = storage.get_webhook_deliveries("webhook-1").pop().expect("delivery queued");
+        let client = reqwest::Client::new();
+        
+        // A failure is retried after the base backoff
+        WebhookService::attempt(&storage, &client, delivery.clone()).await;
+        let retried = storage.get_webhook_delivery(&delivery.id).unwrap();
+        assert!(matches!(retried.status, DeliveryStatus::Pending));
+        assert_eq!(retried.attempts[0].status_code, Some(500));
+        let backoff = retried.next_attempt_at.unwrap() - retried.attempts[0].attempted_at;
+        assert!((backoff.num_seconds() - WebhookService::BASE_BACKOFF_SECS).abs() <= 1, "{backoff}");
+        
+        // The last allowed attempt failing gives up
+        WebhookService::attempt(&storage, &client, WebhookDelivery { retries: WebhookService::MAX_RETRIES - 1, ..retried }).await;
+        let failed = storage.get_webhook_delivery(&delivery.id).unwrap();
+        assert!(matches!(failed.status, DeliveryStatus::Failed));
+        assert_eq!(failed.next_attempt_at, None);
+        
+        WebhookService::attempt(&storage, &client, delivery.clone()).await;
+        assert!(matches!(storage.get_webhook_delivery(&delivery.id).unwrap().status, DeliveryStatus::Delivered));
+        
+        let requests = receiver.await.unwrap();
+        for (head, body) in &requests {
+            assert!(head.contains("x-verichain-event: shipment.created"));
+            let signature = head.lines().find_map(|line| line.strip_prefix("x-verichain-signature: ")).expect("signed");
+            let timestamp: i64 = signature.strip_prefix("t=").and_then(|rest| rest.split(',').next()).unwrap().parse().unwrap();
+            assert_eq!(signature, WebhookService::sign("whsec_test", timestamp, body));
+        }
+        let payload: Value = serde_json::from_slice(&requests[0].1).unwrap();
+        assert_eq!(payload["type"], "shipment.created");
+        assert_eq!(payload["data"]["shipment_id"], "ship-001");
+    }
+
+    #[actix_web::test]
+    async fn a_hanging_receiver_only_delays_its_own_deliveries() {
+        // Accepts connections and never answers
+        let hanging = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hanging_url = format!("http://{}/hook", hanging.local_addr().unwrap());
+        actix_web::rt::spawn(async move {
+            let mut sockets = Vec::new();
+            loop {
+                sockets.push(hanging.accept().await.unwrap().0);
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = actix_web::rt::spawn(receive_webhooks(listener, &["204 No Content"]));
+        
+        let storage = Arc::new(Storage::new());
+        storage.initialize_mock_data().await;
+        for (id, url) in [("webhook-hanging", hanging_url), ("webhook-fast", url)] {
+            storage.add_webhook(WebhookSubscription {
+                id: id.to_string(),
+                user_id: "cons-001".to_string(),
+                url,
+                events: vec![WebhookEventType::ShipmentCreated],
+                secret: "whsec_test".to_string(),
+                created_at: Utc::now(),
+            }).unwrap();
+        }
+        let shipment = storage.get_shipment("ship-001").unwrap();
+        WebhookService::enqueue(&storage, &ShipmentUpdate {
+            shipment_id: shipment.id.clone(),
+            timestamp: Utc::now(),
+            change: ShipmentChange::Created,
+            shipment,
+        });
+        let hanging_delivery = storage.get_webhook_deliveries("webhook-hanging").pop().expect("delivery queued");
+        let fast_delivery = storage.get_webhook_deliveries("webhook-fast").pop().expect("delivery queued");
+        
+        let busy = Arc::new(Mutex::new(HashSet::new()));
+        WebhookService::dispatch_due(&storage, &reqwest::Client::new(), &busy);
+        let delivered = async {
+            while !matches!(storage.get_webhook_delivery(&fast_delivery.id).unwrap().status, DeliveryStatus::Delivered) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), delivered).await.expect("not held up by the hanging receiver");
+        receiver.await.unwrap();
+        
+        // The hanging subscription is still being drained, so it isn't dispatched twice
+        assert!(busy.lock().unwrap().contains("webhook-hanging"));
+        WebhookService::dispatch_due(&storage, &reqwest::Client::new(), &busy);
+        assert!(storage.get_webhook_delivery(&hanging_delivery.id).unwrap().attempts.is_empty());
+    }
+}
@@ -1,26 +1,154 @@
-use dashmap::DashMap;
-use std::sync::Arc;
-use chrono::Utc;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dashmap::{mapref::entry::Entry, DashMap};
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, NaiveDate, Utc};
+use tokio::sync::{broadcast, Notify};
 use uuid::Uuid;
 
-use crate::models::{Product, Shipment, User, UserRole, ShipmentStatus};
+use crate::errors::ApiError;
+use crate::models::{Device, DeviceStatus, EnvironmentalConstraint, EnvironmentalMetric, IdempotencyRecord, Leg, LineItem, ListQuery, Recall, RecallStatus, Money, Page, Product, SortField, SortOrder, Shipment, ShipmentChange, ShipmentUpdate, TelemetryReading, TelemetrySample, UnitOfMeasure, User, UserRole, ShipmentStatus, StoredResponse, DeliveryStatus, WebhookDelivery, WebhookSubscription};
 
 #[derive(Debug)]
 pub struct Storage {
     pub products: Arc<DashMap<String, Product>>,
     pub shipments: Arc<DashMap<String, Shipment>>,
     pub users: Arc<DashMap<String, User>>,
+    pub devices: Arc<DashMap<String, Device>>,
+    // key hash -> device id
+    pub device_keys: Arc<DashMap<String, String>>,
+    // shipment id -> samples ordered by timestamp
+    pub telemetry: Arc<DashMap<String, Vec<TelemetrySample>>>,
+    pub updates: broadcast::Sender<ShipmentUpdate>,
+    pub webhooks: Arc<DashMap<String, WebhookSubscription>>,
+    pub webhook_deliveries: Arc<DashMap<String, WebhookDelivery>>,
+    webhook_store_path: Option<PathBuf>,
+    webhook_store_lock: Mutex<()>,
+    webhook_store_dirty: Notify,
+    pub idempotency_keys: Arc<DashMap<String, IdempotencyRecord>>,
+    pub recalls: Arc<DashMap<String, Recall>>,
+    /// HMAC key for shipment verification tokens, see `VerificationService`.
+    pub verification_key: Vec<u8>,
+    /// Ed25519 key that signs shipment certificates, see `CertificateService`.
+    pub signing_key: SigningKey,
+    /// Where clients reach the API, used for links printed on labels.
+    pub public_url: String,
+    /// OpenWeather-compatible endpoint that reported temperatures are checked against.
+    pub weather_url: String,
+}
+
+pub enum IdempotencyOutcome {
+    Proceed,
+    Replay(StoredResponse),
+}
+
+/// On-disk form of the webhook subscriptions and delivery queue. Secrets are
+/// kept alongside each subscription since they're never serialized over the API.
+#[derive(Serialize, Deserialize, Default)]
+struct WebhookStoreSnapshot {
+    subscriptions: Vec<PersistedSubscription>,
+    deliveries: Vec<WebhookDelivery>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedSubscription {
+    #[serde(flatten)]
+    subscription: WebhookSubscription,
+    secret: String,
 }
 
 impl Storage {
+    const UPDATE_CHANNEL_CAPACITY: usize = 1024;
+    const MAX_UPDATE_ATTEMPTS: usize = 5;
+    const IDEMPOTENCY_WINDOW_HOURS: i64 = 24;
+    const DEFAULT_PUBLIC_URL: &'static str = "http://localhost:8080";
+    const DEFAULT_WEATHER_URL: &'static str = "https://api.openweathermap.org/data/2.5/weather";
+    const WEBHOOK_STORE_DEBOUNCE: Duration = Duration::from_secs(1);
+    const DELIVERY_RETENTION_DAYS: i64 = 30;
+    const MAX_SETTLED_DELIVERIES: usize = 100;
+    
     pub fn new() -> Self {
+        let (updates, _) = broadcast::channel(Self::UPDATE_CHANNEL_CAPACITY);
         Self {
             products: Arc::new(DashMap::new()),
             shipments: Arc::new(DashMap::new()),
             users: Arc::new(DashMap::new()),
+            devices: Arc::new(DashMap::new()),
+            device_keys: Arc::new(DashMap::new()),
+            telemetry: Arc::new(DashMap::new()),
+            updates,
+            webhooks: Arc::new(DashMap::new()),
+            webhook_deliveries: Arc::new(DashMap::new()),
+            webhook_store_path: None,
+            webhook_store_lock: Mutex::new(()),
+            webhook_store_dirty: Notify::new(),
+            idempotency_keys: Arc::new(DashMap::new()),
+            recalls: Arc::new(DashMap::new()),
+            verification_key: rand::random::<[u8; 32]>().to_vec(),
+            signing_key: SigningKey::from_bytes(&rand::random()),
+            public_url: Self::DEFAULT_PUBLIC_URL.to_string(),
+            weather_url: Self::DEFAULT_WEATHER_URL.to_string(),
         }
     }
     
+    /// Uses a fixed key for verification tokens so printed QR codes stay
+    /// valid across restarts. Without one, a random key is used per process.
+    pub fn with_verification_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.verification_key = key.into();
+        self
+    }
+    
+    /// Signs certificates with a fixed key so the published public key stays
+    /// valid across restarts.
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.signing_key = key;
+        self
+    }
+    
+    /// Sets the base URL that verification links point at, e.g.
+    /// `https://verify.example.com`, rather than trusting request headers.
+    pub fn with_public_url(mut self, url: impl Into<String>) -> Self {
+        self.public_url = url.into().trim_end_matches('/').to_string();
+        self
+    }
+    
+    /// Points temperature verification at another OpenWeather-compatible
+    /// endpoint, e.g. a proxy or a fake in tests.
+    pub fn with_weather_url(mut self, url: impl Into<String>) -> Self {
+        self.weather_url = url.into();
+        self
+    }
+    
+    /// Persists webhook subscriptions and the delivery queue to `path`,
+    /// restoring whatever was saved there by a previous run.
+    pub fn with_webhook_store(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        
+        match std::fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice::<WebhookStoreSnapshot>(&bytes) {
+                Ok(snapshot) => {
+                    for persisted in snapshot.subscriptions {
+                        let mut subscription = persisted.subscription;
+                        subscription.secret = persisted.secret;
+                        self.webhooks.insert(subscription.id.clone(), subscription);
+                    }
+                    for delivery in snapshot.deliveries {
+                        self.webhook_deliveries.insert(delivery.id.clone(), delivery);
+                    }
+                }
+                Err(e) => log::error!("Ignoring unreadable webhook store {}: {}", path.display(), e),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to read webhook store {}: {}", path.display(), e),
+        }
+        
+        self.webhook_store_path = Some(path);
+        self
+    }
+    
     pub async fn initialize_mock_data(&self) {
         // Create mock users
         let manufacturer = User {
@@ -56,8 +184,28 @@ impl Storage {
             manufacturer: manufacturer.id.clone(),
             min_temperature: 2.0,
             max_temperature: 8.0,
+            excursion_budget_minutes: 30.0,
+            constraints: vec![
+                EnvironmentalConstraint {
+                    metric: EnvironmentalMetric::Humidity,
+                    min: Some(30.0),
+                    max: Some(65.0),
+                },
+                EnvironmentalConstraint {
+                    metric: EnvironmentalMetric::Shock,
+                    min: None,
+                    max: Some(5.0),
+                },
+            ],
             logistics_partner: logistics.id.clone(),
+            gtin: Some("09506000134352".to_string()),
+            unit_price: Some(Money {
+                amount_minor: 50_000,
+                currency: Money::DEFAULT_CURRENCY.to_string(),
+            }),
             registered_at: Utc::now(),
+            updated_at: None,
+            deleted_at: None,
         };
         
         self.products.insert(product.id.clone(), product.clone());
@@ -68,15 +216,36 @@ impl Storage {
             product_id: product.id.clone(),
             product: product.clone(),
             manufacturer: manufacturer.id,
-            logistics_partner: logistics.id,
+            logistics_partner: logistics.id.clone(),
             consumer: consumer.id,
             status: ShipmentStatus::Pending,
-            escrow_amount: 50000.0,
+            escrow: Money {
+                amount_minor: 5_000_000,
+                currency: Money::DEFAULT_CURRENCY.to_string(),
+            },
             escrow_released: false,
+            escrow_refund: None,
+            line_items: vec![LineItem {
+                product_id: product.id.clone(),
+                product: product.clone(),
+                lot: Some("LOT-2024-001".to_string()),
+                expiry: NaiveDate::from_ymd_opt(2027, 6, 30),
+                serials: vec![],
+                quantity: 100.0,
+                unit: UnitOfMeasure::Each,
+                unit_price: product.unit_price.clone(),
+            }],
             events: vec![],
+            recalls: vec![],
+            legs: vec![Leg::new(logistics.id)],
+            compromised_leg: None,
+            compromised_at: None,
+            cancellation: None,
+            return_details: None,
             created_at: Utc::now(),
             delivered_at: None,
             confirmed_at: None,
+            version: 0,
         };
         
         self.shipments.insert(shipment.id.clone(), shipment);
@@ -84,66 +253,198 @@ impl Storage {
         println!("✅ Mock data initialized successfully");
     }
     
-    pub fn get_products(&self) -> Vec<Product> {
-        self.products.iter().map(|entry| entry.value().clone()).collect()
+    pub fn list_products(&self, query: &ListQuery) -> Result<Page<Product>, ApiError> {
+        if matches!(query.sort, Some(SortField::CreatedAt)) {
+            return Err(ApiError::Validation("Products can only be sorted by registered_at".to_string()));
+        }
+        
+        let products = self
+            .products
+            .iter()
+            .filter(|entry| query.matches_product(entry.value()))
+            .map(|entry| entry.value().clone())
+            .collect();
+        paginate(products, |product| (product.registered_at.timestamp_millis(), product.id.clone()), query)
     }
     
-    pub fn get_shipments(&self) -> Vec<Shipment> {
-        self.shipments.iter().map(|entry| entry.value().clone()).collect()
+    pub fn list_shipments(&self, query: &ListQuery) -> Result<Page<Shipment>, ApiError> {
+        self.list_shipments_where(query, |_| true)
     }
     
-    pub fn get_users(&self) -> Vec<User> {
-        self.users.iter().map(|entry| entry.value().clone()).collect()
+    /// Lists the shipments `user_id` takes part in, in `query.role` or any role.
+    pub fn list_shipments_by_user(&self, user_id: &str, query: &ListQuery) -> Result<Page<Shipment>, ApiError> {
+        let roles = match &query.role {
+            Some(role) => vec![role.as_str()],
+            None => vec!["manufacturer", "logistics", "consumer"],
+        };
+        self.list_shipments_where(query, |shipment| roles.iter().any(|role| shipment.involves(user_id, role)))
     }
     
-    pub fn get_shipments_by_user(&self, user_id: &str, role: &str) -> Vec<Shipment> {
-        self.shipments
+    fn list_shipments_where(&self, query: &ListQuery, predicate: impl Fn(&Shipment) -> bool) -> Result<Page<Shipment>, ApiError> {
+        if matches!(query.sort, Some(SortField::RegisteredAt)) {
+            return Err(ApiError::Validation("Shipments can only be sorted by created_at".to_string()));
+        }
+        
+        let shipments = self
+            .shipments
             .iter()
-            .filter_map(|entry| {
-                let shipment = entry.value();
-                match role {
-                    "manufacturer" => {
-                        if shipment.manufacturer == user_id {
-                            Some(shipment.clone())
-                        } else {
-                            None
-                        }
-                    }
-                    "logistics" => {
-                        if shipment.logistics_partner == user_id {
-                            Some(shipment.clone())
-                        } else {
-                            None
-                        }
-                    }
-                    "consumer" => {
-                        if shipment.consumer == user_id {
-                            Some(shipment.clone())
-                        } else {
-                            None
-                        }
-                    }
-                    _ => None,
-                }
-            })
-            .collect()
+            .filter(|entry| query.matches_shipment(entry.value()) && predicate(entry.value()))
+            .map(|entry| entry.value().clone())
+            .collect();
+        paginate(shipments, |shipment| (shipment.created_at.timestamp_millis(), shipment.id.clone()), query)
     }
     
-    pub fn add_product(&self, product: Product) -> Result<Product, String> {
+    pub fn list_users(&self, query: &ListQuery) -> Result<Page<User>, ApiError> {
+        if query.sort.is_some() {
+            return Err(ApiError::Validation("Users are listed by id and cannot be sorted".to_string()));
+        }
+        
+        let users = self
+            .users
+            .iter()
+            .filter(|entry| query.matches_user(entry.value()))
+            .map(|entry| entry.value().clone())
+            .collect();
+        paginate(users, |user| (0, user.id.clone()), query)
+    }
+    
+    pub fn add_product(&self, product: Product) -> Result<Product, ApiError> {
+        if let Some(gtin) = &product.gtin {
+            let taken = self
+                .products
+                .iter()
+                .any(|existing| existing.deleted_at.is_none() && existing.gtin.as_ref() == Some(gtin));
+            if taken {
+                return Err(ApiError::Conflict(format!("GTIN {} is already registered", gtin)));
+            }
+        }
         self.products.insert(product.id.clone(), product.clone());
         Ok(product)
     }
     
-    pub fn add_shipment(&self, shipment: Shipment) -> Result<Shipment, String> {
+    /// Updates the catalogue entry only; shipments keep the snapshot taken
+    /// when they were funded.
+    pub fn update_product(&self, id: &str, name: Option<String>, description: Option<String>) -> Result<Product, ApiError> {
+        let mut product = self
+            .products
+            .get_mut(id)
+            .ok_or_else(|| ApiError::not_found("Product"))?;
+        if product.deleted_at.is_some() {
+            return Err(ApiError::Conflict("Product has been deleted".to_string()));
+        }
+        
+        if let Some(name) = name {
+            product.name = name;
+        }
+        if let Some(description) = description {
+            product.description = description;
+        }
+        product.updated_at = Some(Utc::now());
+        Ok(product.clone())
+    }
+    
+    pub fn delete_product(&self, id: &str) -> Result<Product, ApiError> {
+        let mut product = self
+            .products
+            .get_mut(id)
+            .ok_or_else(|| ApiError::not_found("Product"))?;
+        
+        if product.deleted_at.is_none() {
+            product.deleted_at = Some(Utc::now());
+        }
+        Ok(product.clone())
+    }
+    
+    pub fn add_shipment(&self, shipment: Shipment) -> Result<Shipment, ApiError> {
         self.shipments.insert(shipment.id.clone(), shipment.clone());
+        self.publish(&shipment, ShipmentChange::Created);
         Ok(shipment)
     }
     
-    pub fn update_shipment(&self, shipment: Shipment) -> Result<Shipment, String> {
-        self.shipments.insert(shipment.id.clone(), shipment.clone());
+    /// Compare-and-swap: stores `shipment` only if the stored copy is still at
+    /// `shipment.version`, bumping the version. Fails with `Conflict` otherwise.
+    pub fn update_shipment(&self, mut shipment: Shipment) -> Result<Shipment, ApiError> {
+        let previous = {
+            let mut current = self
+                .shipments
+                .get_mut(&shipment.id)
+                .ok_or_else(|| ApiError::not_found("Shipment"))?;
+            if current.version != shipment.version {
+                return Err(ApiError::Conflict("Shipment was modified concurrently".to_string()));
+            }
+            
+            shipment.version += 1;
+            std::mem::replace(current.value_mut(), shipment.clone())
+        };
+        
+        self.publish_changes(&previous, &shipment);
         Ok(shipment)
     }
     
+    /// Applies `change` to the latest copy of a shipment, re-reading and
+    /// retrying when a concurrent writer wins the compare-and-swap.
+    pub fn modify_shipment(
+        &self,
+        id: &str,
+        mut change: impl FnMut(&mut Shipment) -> Result<(), ApiError>,
+    ) -> Result<Shipment, ApiError> {
+        for _ in 0..Self::MAX_UPDATE_ATTEMPTS {
+            let mut shipment = self.get_shipment(id).ok_or_else(|| ApiError::not_found("Shipment"))?;
+            change(&mut shipment)?;
+            
+            match self.update_shipment(shipment) {
+                Err(ApiError::Conflict(_)) => continue,
+                result => return result,
+            }
+        }
+        Err(ApiError::Conflict("Shipment is being modified too frequently, try again".to_string()))
+    }
+    
+    pub fn subscribe(&self) -> broadcast::Receiver<ShipmentUpdate> {
+        self.updates.subscribe()
+    }
+    
+    fn publish_changes(&self, previous: &Shipment, current: &Shipment) {
+        for event in current.events.iter().skip(previous.events.len()) {
+            self.publish(current, ShipmentChange::EventAdded { event: event.clone() });
+        }
+        if previous.status != current.status {
+            self.publish(current, ShipmentChange::StatusChanged {
+                from: previous.status.clone(),
+                to: current.status.clone(),
+            });
+        }
+        if !previous.escrow_released && current.escrow_released {
+            // After a return, the manufacturer only gets what wasn't refunded
+            let amount = match &current.escrow_refund {
+                Some(refund) => Money {
+                    amount_minor: current.escrow.amount_minor - refund.amount_minor,
+                    currency: current.escrow.currency.clone(),
+                },
+                None => current.escrow.clone(),
+            };
+            self.publish(current, ShipmentChange::EscrowReleased { amount });
+        }
+        if previous.escrow_refund.is_none() {
+            if let Some(refund) = &current.escrow_refund {
+                self.publish(current, ShipmentChange::EscrowRefunded { amount: refund.clone() });
+            }
+        }
+        for recall_id in current.recalls.iter().filter(|id| !previous.recalls.contains(id)) {
+            self.publish(current, ShipmentChange::Recalled { recall_id: recall_id.clone() });
+        }
+    }
+    
+    fn publish(&self, shipment: &Shipment, change: ShipmentChange) {
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.updates.send(ShipmentUpdate {
+            shipment_id: shipment.id.clone(),
+            timestamp: Utc::now(),
+            change,
+            shipment: shipment.clone(),
+        });
+    }
+    
     pub fn get_product(&self, id: &str) -> Option<Product> {
         self.products.get(id).map(|entry| entry.value().clone())
     }
@@ -151,4 +452,569 @@ impl Storage {
     pub fn get_shipment(&self, id: &str) -> Option<Shipment> {
         self.shipments.get(id).map(|entry| entry.value().clone())
     }
-}
\ No newline at end of file
+    
+    /// Shipments with a line item for `product_id`, oldest first.
+    pub fn get_shipments_with_product(&self, product_id: &str) -> Vec<Shipment> {
+        let mut shipments: Vec<Shipment> = self
+            .shipments
+            .iter()
+            .filter(|entry| entry.line_items.iter().any(|item| item.product_id == product_id))
+            .map(|entry| entry.value().clone())
+            .collect();
+        shipments.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        shipments
+    }
+    
+    pub fn get_user(&self, id: &str) -> Option<User> {
+        self.users.get(id).map(|entry| entry.value().clone())
+    }
+    
+    pub fn add_device(&self, device: Device) -> Result<Device, ApiError> {
+        let owner = self
+            .get_user(&device.owner)
+            .ok_or_else(|| ApiError::Validation(format!("User {} not found", device.owner)))?;
+        if !matches!(owner.role, UserRole::Logistics) {
+            return Err(ApiError::Validation("Devices can only be registered to logistics partners".to_string()));
+        }
+        
+        self.device_keys.insert(device.key_hash.clone(), device.id.clone());
+        self.devices.insert(device.id.clone(), device.clone());
+        Ok(device)
+    }
+    
+    pub fn get_device(&self, id: &str) -> Option<Device> {
+        self.devices.get(id).map(|entry| entry.value().clone())
+    }
+    
+    pub fn get_devices_by_owner(&self, owner: &str) -> Vec<Device> {
+        self.devices
+            .iter()
+            .filter(|entry| entry.value().owner == owner)
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+    
+    pub fn rotate_device_key(&self, id: &str, key_prefix: String, key_hash: String) -> Result<Device, ApiError> {
+        let mut device = self
+            .devices
+            .get_mut(id)
+            .ok_or_else(|| ApiError::not_found("Device"))?;
+        if matches!(device.status, DeviceStatus::Revoked) {
+            return Err(ApiError::Conflict("Device has been revoked".to_string()));
+        }
+        
+        self.device_keys.remove(&device.key_hash);
+        self.device_keys.insert(key_hash.clone(), device.id.clone());
+        device.key_prefix = key_prefix;
+        device.key_hash = key_hash;
+        device.rotated_at = Some(Utc::now());
+        Ok(device.clone())
+    }
+    
+    pub fn revoke_device(&self, id: &str) -> Result<Device, ApiError> {
+        let mut device = self
+            .devices
+            .get_mut(id)
+            .ok_or_else(|| ApiError::not_found("Device"))?;
+        
+        self.device_keys.remove(&device.key_hash);
+        if !matches!(device.status, DeviceStatus::Revoked) {
+            device.status = DeviceStatus::Revoked;
+            device.revoked_at = Some(Utc::now());
+        }
+        Ok(device.clone())
+    }
+    
+    /// Resolves an API key hash to its active device and records it as seen.
+    pub fn touch_device_by_key(&self, key_hash: &str) -> Option<Device> {
+        let device_id = self.device_keys.get(key_hash)?.value().clone();
+        let mut device = self.devices.get_mut(&device_id)?;
+        if !matches!(device.status, DeviceStatus::Active) {
+            return None;
+        }
+        
+        device.last_seen_at = Some(Utc::now());
+        Some(device.clone())
+    }
+    
+    pub fn add_telemetry(&self, shipment_id: &str, readings: &[TelemetryReading]) -> usize {
+        let mut samples = self.telemetry.entry(shipment_id.to_string()).or_default();
+        samples.extend(readings.iter().map(TelemetrySample::from));
+        samples.sort_by_key(|sample| sample.timestamp_ms);
+        readings.len()
+    }
+    
+    pub fn get_telemetry(&self, shipment_id: &str) -> Vec<TelemetryReading> {
+        self.telemetry
+            .get(shipment_id)
+            .map(|samples| samples.iter().map(TelemetryReading::from).collect())
+            .unwrap_or_default()
+    }
+    
+    pub fn add_recall(&self, recall: Recall) -> Recall {
+        self.recalls.insert(recall.id.clone(), recall.clone());
+        recall
+    }
+    
+    pub fn get_recall(&self, id: &str) -> Option<Recall> {
+        self.recalls.get(id).map(|entry| entry.value().clone())
+    }
+    
+    /// All recalls, newest first.
+    pub fn list_recalls(&self) -> Vec<Recall> {
+        let mut recalls: Vec<Recall> = self.recalls.iter().map(|entry| entry.value().clone()).collect();
+        recalls.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+        recalls
+    }
+    
+    /// An open recall covering `item` if it were shipped on `shipped_on`.
+    pub fn find_open_recall(&self, item: &LineItem, shipped_on: NaiveDate) -> Option<Recall> {
+        self.recalls
+            .iter()
+            .find(|entry| entry.status == RecallStatus::Open && entry.covers(item, shipped_on))
+            .map(|entry| entry.value().clone())
+    }
+    
+    pub fn close_recall(&self, id: &str) -> Result<Recall, ApiError> {
+        let mut recall = self.recalls.get_mut(id).ok_or_else(|| ApiError::not_found("Recall"))?;
+        if recall.status == RecallStatus::Closed {
+            return Err(ApiError::Conflict("Recall is already closed".to_string()));
+        }
+        
+        recall.status = RecallStatus::Closed;
+        recall.closed_at = Some(Utc::now());
+        Ok(recall.clone())
+    }
+    
+    pub fn add_webhook(&self, subscription: WebhookSubscription) -> Result<WebhookSubscription, ApiError> {
+        if self.get_user(&subscription.user_id).is_none() {
+            return Err(ApiError::not_found("User"));
+        }
+        
+        self.webhooks.insert(subscription.id.clone(), subscription.clone());
+        self.persist_webhooks();
+        Ok(subscription)
+    }
+    
+    pub fn get_webhook(&self, id: &str) -> Option<WebhookSubscription> {
+        self.webhooks.get(id).map(|entry| entry.value().clone())
+    }
+    
+    pub fn get_webhooks_by_user(&self, user_id: &str) -> Vec<WebhookSubscription> {
+        self.webhooks
+            .iter()
+            .filter(|entry| entry.value().user_id == user_id)
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+    
+    pub fn remove_webhook(&self, id: &str) -> Option<WebhookSubscription> {
+        let removed = self.webhooks.remove(id).map(|(_, subscription)| subscription);
+        if removed.is_some() {
+            self.persist_webhooks();
+        }
+        removed
+    }
+    
+    pub fn upsert_webhook_delivery(&self, delivery: WebhookDelivery) -> WebhookDelivery {
+        if self.webhook_deliveries.insert(delivery.id.clone(), delivery.clone()).is_none() {
+            self.prune_webhook_deliveries(&delivery.subscription_id);
+        }
+        self.persist_webhooks();
+        delivery
+    }
+    
+    /// Drops settled deliveries past the retention window, and beyond the
+    /// newest `MAX_SETTLED_DELIVERIES` of the subscription. Pending ones are kept.
+    fn prune_webhook_deliveries(&self, subscription_id: &str) {
+        let cutoff = Utc::now() - chrono::Duration::days(Self::DELIVERY_RETENTION_DAYS);
+        self.webhook_deliveries
+            .retain(|_, delivery| matches!(delivery.status, DeliveryStatus::Pending) || delivery.created_at >= cutoff);
+        
+        let mut settled: Vec<(DateTime<Utc>, String)> = self
+            .webhook_deliveries
+            .iter()
+            .filter(|entry| entry.value().subscription_id == subscription_id && !matches!(entry.value().status, DeliveryStatus::Pending))
+            .map(|entry| (entry.value().created_at, entry.key().clone()))
+            .collect();
+        if settled.len() > Self::MAX_SETTLED_DELIVERIES {
+            settled.sort_by(|a, b| b.cmp(a));
+            for (_, id) in settled.drain(Self::MAX_SETTLED_DELIVERIES..) {
+                self.webhook_deliveries.remove(&id);
+            }
+        }
+    }
+    
+    pub fn get_webhook_delivery(&self, id: &str) -> Option<WebhookDelivery> {
+        self.webhook_deliveries.get(id).map(|entry| entry.value().clone())
+    }
+    
+    /// A subscription's deliveries, newest first.
+    pub fn get_webhook_deliveries(&self, subscription_id: &str) -> Vec<WebhookDelivery> {
+        let mut deliveries: Vec<WebhookDelivery> = self
+            .webhook_deliveries
+            .iter()
+            .filter(|entry| entry.value().subscription_id == subscription_id)
+            .map(|entry| entry.value().clone())
+            .collect();
+        deliveries.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+        deliveries
+    }
+    
+    /// Pending deliveries whose next attempt is due, oldest first.
+    pub fn get_due_webhook_deliveries(&self) -> Vec<WebhookDelivery> {
+        let now = Utc::now();
+        let mut due: Vec<WebhookDelivery> = self
+            .webhook_deliveries
+            .iter()
+            .filter(|entry| entry.value().next_attempt_at.is_some_and(|at| at <= now))
+            .map(|entry| entry.value().clone())
+            .collect();
+        due.sort_by_key(|delivery| delivery.next_attempt_at);
+        due
+    }
+    
+    /// Marks the webhook store as changed for `run_webhook_store_writer`.
+    fn persist_webhooks(&self) {
+        if self.webhook_store_path.is_some() {
+            self.webhook_store_dirty.notify_one();
+        }
+    }
+    
+    /// Writes the webhook store after changes, batching those that arrive
+    /// within `WEBHOOK_STORE_DEBOUNCE` and keeping file I/O off the runtime.
+    pub async fn run_webhook_store_writer(storage: Arc<Storage>) {
+        loop {
+            storage.webhook_store_dirty.notified().await;
+            tokio::time::sleep(Self::WEBHOOK_STORE_DEBOUNCE).await;
+            
+            let writer = storage.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || writer.write_webhook_store()).await {
+                log::error!("Webhook store writer panicked: {}", e);
+            }
+        }
+    }
+    
+    /// Writes the webhook store now, e.g. on shutdown.
+    pub fn write_webhook_store(&self) {
+        let Some(path) = &self.webhook_store_path else {
+            return;
+        };
+        let _guard = self.webhook_store_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        let snapshot = WebhookStoreSnapshot {
+            subscriptions: self
+                .webhooks
+                .iter()
+                .map(|entry| PersistedSubscription {
+                    subscription: entry.value().clone(),
+                    secret: entry.value().secret.clone(),
+                })
+                .collect(),
+            deliveries: self.webhook_deliveries.iter().map(|entry| entry.value().clone()).collect(),
+        };
+        
+        // Write to a sibling file and rename so a crash never leaves a torn store
+        let tmp_path = path.with_extension("tmp");
+        let result = serde_json::to_vec(&snapshot)
+            .map_err(std::io::Error::from)
+            .and_then(|bytes| std::fs::write(&tmp_path, bytes))
+            .and_then(|_| std::fs::rename(&tmp_path, path));
+        if let Err(e) = result {
+            log::error!("Failed to persist webhook store {}: {}", path.display(), e);
+        }
+    }
+    
+    /// Claims `key` for a request with the given fingerprint, or returns the
+    /// stored response of an earlier identical request within the window.
+    pub fn begin_idempotent_request(&self, key: &str, fingerprint: &str) -> Result<IdempotencyOutcome, ApiError> {
+        let now = Utc::now();
+        let window = chrono::Duration::hours(Self::IDEMPOTENCY_WINDOW_HOURS);
+        self.idempotency_keys.retain(|_, record| now - record.created_at < window);
+        
+        let record = IdempotencyRecord {
+            fingerprint: fingerprint.to_string(),
+            created_at: now,
+            response: None,
+        };
+        match self.idempotency_keys.entry(key.to_string()) {
+            Entry::Occupied(entry) => {
+                let existing = entry.get();
+                if existing.fingerprint != fingerprint {
+                    return Err(ApiError::IdempotencyKeyReused(
+                        "Idempotency-Key was already used for a different request".to_string(),
+                    ));
+                }
+                match &existing.response {
+                    Some(response) => Ok(IdempotencyOutcome::Replay(response.clone())),
+                    None => Err(ApiError::Conflict("A request with this Idempotency-Key is still in progress".to_string())),
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(record);
+                Ok(IdempotencyOutcome::Proceed)
+            }
+        }
+    }
+    
+    pub fn complete_idempotent_request(&self, key: &str, response: StoredResponse) {
+        if let Some(mut record) = self.idempotency_keys.get_mut(key) {
+            record.response = Some(response);
+        }
+    }
+    
+    pub fn abort_idempotent_request(&self, key: &str) {
+        self.idempotency_keys.remove_if(key, |_, record| record.response.is_none());
+    }
+}
+
+/// Keyset pagination over `(timestamp millis, id)` sort keys. The cursor is an
+/// opaque encoding of the last key returned, so pages stay stable while new
+/// records are inserted.
+fn paginate<T>(mut items: Vec<T>, key: impl Fn(&T) -> (i64, String), query: &ListQuery) -> Result<Page<T>, ApiError> {
+    items.sort_by_key(|item| key(item));
+    if matches!(query.order, SortOrder::Desc) {
+        items.reverse();
+    }
+    
+    if let Some(cursor) = &query.cursor {
+        let after = decode_cursor(cursor).ok_or_else(|| ApiError::Validation("Invalid cursor".to_string()))?;
+        items.retain(|item| match query.order {
+            SortOrder::Asc => key(item) > after,
+            SortOrder::Desc => key(item) < after,
+        });
+    }
+    
+    let limit = query.limit.unwrap_or(ListQuery::DEFAULT_LIMIT).clamp(1, ListQuery::MAX_LIMIT);
+    let next_cursor = if items.len() > limit {
+        items.truncate(limit);
+        items.last().map(|item| encode_cursor(&key(item)))
+    } else {
+        None
+    };
+    
+    Ok(Page { items, next_cursor })
+}
+
+fn encode_cursor((timestamp, id): &(i64, String)) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}:{}", timestamp, id))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(i64, String)> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    let (timestamp, id) = decoded.split_once(':')?;
+    Some((timestamp.parse().ok()?, id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::WebhookEventType;
+
+    fn delivery(id: usize, status: DeliveryStatus, age_days: i64) -> WebhookDelivery {
+        WebhookDelivery {
+            id: format!("delivery-{:03}", id),
+            subscription_id: "webhook-1".to_string(),
+            event_type: WebhookEventType::ShipmentCreated,
+            payload: serde_json::Value::Null,
+            status,
+            retries: 0,
+            next_attempt_at: None,
+            attempts: vec![],
+            created_at: Utc::now() - chrono::Duration::days(age_days) + chrono::Duration::seconds(id as i64),
+        }
+    }
+
+    fn keyed(items: &[(i64, &str)]) -> Vec<(i64, String)> {
+        items.iter().map(|(timestamp, id)| (*timestamp, id.to_string())).collect()
+    }
+
+    fn page_ids(page: &Page<(i64, String)>) -> Vec<&str> {
+        page.items.iter().map(|(_, id)| id.as_str()).collect()
+    }
+
+    #[test]
+    fn cursors_stay_put_while_records_are_inserted() {
+        let mut items = keyed(&[(1, "a"), (2, "b"), (2, "c"), (3, "d"), (4, "e")]);
+        let mut query = ListQuery { limit: Some(2), ..Default::default() };
+        
+        let first = paginate(items.clone(), Clone::clone, &query).unwrap();
+        assert_eq!(page_ids(&first), ["e", "d"]);
+        
+        // Newer records, and one sharing the cursor's timestamp but sorting before it, land on earlier pages
+        items.extend(keyed(&[(5, "f"), (3, "a"), (3, "z")]));
+        query.cursor = first.next_cursor;
+        let second = paginate(items.clone(), Clone::clone, &query).unwrap();
+        assert_eq!(page_ids(&second), ["a", "c"]);
+        
+        query.cursor = second.next_cursor;
+        let last = paginate(items.clone(), Clone::clone, &query).unwrap();
+        assert_eq!(page_ids(&last), ["b", "a"]);
+        assert_eq!(last.next_cursor, None);
+        
+        query.order = SortOrder::Asc;
+        query.cursor = None;
+        let ascending = paginate(items, Clone::clone, &query).unwrap();
+        assert_eq!(page_ids(&ascending), ["a", "b"]);
+    }
+
+    #[test]
+    fn limits_are_clamped_and_cursors_checked() {
+        let items: Vec<(i64, String)> = (0..300).map(|i| (i, format!("item-{:03}", i))).collect();
+        let page = |limit: Option<usize>| {
+            let query = ListQuery { limit, ..Default::default() };
+            paginate(items.clone(), Clone::clone, &query).unwrap().items.len()
+        };
+        assert_eq!(page(None), ListQuery::DEFAULT_LIMIT);
+        assert_eq!(page(Some(0)), 1);
+        assert_eq!(page(Some(10_000)), ListQuery::MAX_LIMIT);
+        
+        let query = ListQuery { cursor: Some("not a cursor".to_string()), ..Default::default() };
+        assert!(matches!(paginate(items, Clone::clone, &query), Err(ApiError::Validation(_))));
+    }
+
+    #[actix_web::test]
+    async fn filters_combine_with_the_time_range_across_pages() {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        let template = storage.get_shipment("ship-001").unwrap();
+        let start = Utc::now() - chrono::Duration::days(10);
+        for day in 0..10 {
+            storage.shipments.insert(format!("ship-day-{}", day), Shipment {
+                id: format!("ship-day-{}", day),
+                status: if day % 2 == 0 { ShipmentStatus::InTransit } else { ShipmentStatus::Delivered },
+                created_at: start + chrono::Duration::days(day),
+                ..template.clone()
+            });
+        }
+        
+        let mut query = ListQuery {
+            status: Some(ShipmentStatus::InTransit),
+            from: Some(start + chrono::Duration::days(2)),
+            to: Some(start + chrono::Duration::days(8)),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let mut ids = Vec::new();
+        loop {
+            let page = storage.list_shipments(&query).unwrap();
+            ids.extend(page.items.into_iter().map(|shipment| shipment.id));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(ids, ["ship-day-8", "ship-day-6", "ship-day-4", "ship-day-2"]);
+    }
+
+    #[actix_web::test]
+    async fn compromised_shipments_stay_listed_once_returned() {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        let template = storage.get_shipment("ship-001").unwrap();
+        for (id, status, compromised_at) in [
+            ("ship-compromised", ShipmentStatus::Compromised, Some(Utc::now())),
+            ("ship-returned", ShipmentStatus::Returned, Some(Utc::now())),
+            ("ship-sound-return", ShipmentStatus::Returned, None),
+        ] {
+            storage.shipments.insert(id.to_string(), Shipment { id: id.to_string(), status, compromised_at, ..template.clone() });
+        }
+        
+        let query = ListQuery { compromised_only: true, ..Default::default() };
+        let mut ids: Vec<String> = storage.list_shipments(&query).unwrap().items.into_iter().map(|shipment| shipment.id).collect();
+        ids.sort();
+        assert_eq!(ids, ["ship-compromised", "ship-returned"]);
+    }
+
+    #[actix_web::test]
+    async fn carriers_find_shipments_on_any_of_their_legs() {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        storage
+            .modify_shipment("ship-001", |shipment| {
+                shipment.legs.push(Leg::new("logi-002".to_string()));
+                Ok(())
+            })
+            .unwrap();
+        
+        for partner in ["logi-001", "logi-002"] {
+            let query = ListQuery { logistics_partner: Some(partner.to_string()), ..Default::default() };
+            let ids: Vec<String> = storage.list_shipments(&query).unwrap().items.into_iter().map(|shipment| shipment.id).collect();
+            assert_eq!(ids, ["ship-001"], "{partner}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn modify_shipment_retries_when_a_concurrent_write_wins() {
+        let storage = Storage::new();
+        storage.initialize_mock_data().await;
+        let version = storage.get_shipment("ship-001").unwrap().version;
+        
+        let mut attempts = 0;
+        let shipment = storage
+            .modify_shipment("ship-001", |shipment| {
+                attempts += 1;
+                if attempts == 1 {
+                    // Another writer gets in between this read and its write
+                    let mut concurrent = storage.get_shipment("ship-001").unwrap();
+                    concurrent.recalls.push("recall-1".to_string());
+                    storage.update_shipment(concurrent).unwrap();
+                }
+                shipment.recalls.push("recall-2".to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(shipment.recalls, ["recall-1", "recall-2"]);
+        assert_eq!(shipment.version, version + 2);
+        
+        // A writer that always loses gives up rather than spinning
+        let result = storage.modify_shipment("ship-001", |_| {
+            let concurrent = storage.get_shipment("ship-001").unwrap();
+            storage.update_shipment(concurrent).unwrap();
+            Ok(())
+        });
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+    }
+
+    #[test]
+    fn old_and_excess_settled_deliveries_are_pruned() {
+        let storage = Storage::new();
+        storage.upsert_webhook_delivery(delivery(0, DeliveryStatus::Failed, 45));
+        storage.upsert_webhook_delivery(delivery(1, DeliveryStatus::Pending, 45));
+        for id in 2..2 + Storage::MAX_SETTLED_DELIVERIES + 5 {
+            storage.upsert_webhook_delivery(delivery(id, DeliveryStatus::Delivered, 1));
+        }
+        
+        let deliveries = storage.get_webhook_deliveries("webhook-1");
+        assert_eq!(deliveries.len(), Storage::MAX_SETTLED_DELIVERIES + 1);
+        assert!(deliveries.iter().any(|delivery| delivery.id == "delivery-001"), "pending deliveries are never pruned");
+        assert!(deliveries.iter().all(|delivery| delivery.id != "delivery-000" && delivery.id != "delivery-002"));
+        assert!(deliveries.windows(2).all(|pair| pair[0].created_at >= pair[1].created_at), "newest first");
+    }
+
+    #[actix_web::test]
+    async fn webhook_store_is_written_in_the_background() {
+        let path = std::env::temp_dir().join(format!("verichain-webhooks-{}.json", Uuid::new_v4()));
+        let storage = Arc::new(Storage::new().with_webhook_store(&path));
+        storage.initialize_mock_data().await;
+        actix_web::rt::spawn(Storage::run_webhook_store_writer(storage.clone()));
+        
+        storage.add_webhook(WebhookSubscription {
+            id: "webhook-1".to_string(),
+            user_id: "cons-001".to_string(),
+            url: "https://example.com/hook".to_string(),
+            events: vec![WebhookEventType::ShipmentCreated],
+            secret: "secret".to_string(),
+            created_at: Utc::now(),
+        }).unwrap();
+        storage.upsert_webhook_delivery(delivery(0, DeliveryStatus::Pending, 0));
+        assert!(!path.exists(), "writes are debounced");
+        
+        tokio::time::sleep(Storage::WEBHOOK_STORE_DEBOUNCE * 2).await;
+        let restored = Storage::new().with_webhook_store(&path);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(restored.get_webhook("webhook-1").map(|webhook| webhook.secret), Some("secret".to_string()));
+        assert!(restored.get_webhook_delivery("delivery-000").is_some());
+    }
+}
@@ -0,0 +1,157 @@
+use serde_json::Value;
+
+use crate::wire::{self, CAMEL_JSON};
+
+/// Renders TypeScript definitions for the component schemas of an OpenAPI
+/// document, in the camelCase/epoch-millis shape served as `wire::CAMEL_JSON`.
+/// Generic instantiations such as `ApiResponse_Product` are left out.
+pub fn render(openapi: &Value) -> String {
+    let mut out = format!(
+        "// Generated from the Rust models by `cargo test`, do not edit.\n\
+         // Shapes match responses requested with `Accept: {}`.\n",
+        CAMEL_JSON
+    );
+    
+    let schemas = openapi["components"]["schemas"].as_object().cloned().unwrap_or_default();
+    for (name, schema) in schemas.iter().filter(|(name, _)| !name.contains('_')) {
+        out.push('\n');
+        out.push_str(&doc_comment(schema, ""));
+        if schema.get("properties").is_some() && schema.get("oneOf").is_none() {
+            out.push_str(&format!("export interface {} {}\n", name, object_type(schema, "")));
+        } else {
+            out.push_str(&format!("export type {} = {};\n", name, ts_type(schema, "")));
+        }
+    }
+    out
+}
+
+fn doc_comment(schema: &Value, indent: &str) -> String {
+    match schema["description"].as_str() {
+        Some(description) => format!("{}/** {} */\n", indent, description.split_whitespace().collect::<Vec<_>>().join(" ")),
+        None => String::new(),
+    }
+}
+
+fn ts_type(schema: &Value, indent: &str) -> String {
+    if let Some(reference) = schema["$ref"].as_str() {
+        return reference.rsplit('/').next().unwrap_or(reference).to_string();
+    }
+    if let Some(variants) = schema["oneOf"].as_array() {
+        return join(variants.iter().map(|variant| ts_type(variant, indent)), " | ");
+    }
+    if let Some(parts) = schema["allOf"].as_array() {
+        return join(parts.iter().map(|part| ts_type(part, indent)), " & ");
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        return join(values.iter().map(Value::to_string), " | ");
+    }
+    
+    let types: Vec<&str> = match &schema["type"] {
+        Value::String(single) => vec![single.as_str()],
+        Value::Array(many) => many.iter().filter_map(Value::as_str).collect(),
+        _ if schema.get("properties").is_some() => vec!["object"],
+        _ => return "unknown".to_string(),
+    };
+    join(
+        types.into_iter().map(|kind| match kind {
+            "string" if schema["format"] == "date-time" => "number".to_string(),
+            "string" => "string".to_string(),
+            "integer" | "number" => "number".to_string(),
+            "boolean" => "boolean".to_string(),
+            "null" => "null".to_string(),
+            "array" => {
+                let item = ts_type(&schema["items"], indent);
+                if item.contains(' ') { format!("({})[]", item) } else { format!("{}[]", item) }
+            }
+            "object" if schema.get("properties").is_some() => object_type(schema, indent),
+            "object" => match schema.get("additionalProperties") {
+                Some(Value::Object(values)) => format!("Record<string, {}>", ts_type(&Value::Object(values.clone()), indent)),
+                _ => "Record<string, unknown>".to_string(),
+            },
+            _ => "unknown".to_string(),
+        }),
+        " | ",
+    )
+}
+
+fn object_type(schema: &Value, indent: &str) -> String {
+    let required: Vec<&str> = schema["required"]
+        .as_array()
+        .map(|names| names.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let inner = format!("{}  ", indent);
+    
+    let mut out = "{\n".to_string();
+    for (name, property) in schema["properties"].as_object().into_iter().flatten() {
+        let optional = if required.contains(&name.as_str()) { "" } else { "?" };
+        out.push_str(&doc_comment(property, &inner));
+        out.push_str(&format!("{}{}{}: {};\n", inner, wire::camel_case(name), optional, ts_type(property, &inner)));
+    }
+    out.push_str(indent);
+    out.push('}');
+    out
+}
+
+fn join(parts: impl Iterator<Item = String>, separator: &str) -> String {
+    let mut unique: Vec<String> = Vec::new();
+    for part in parts {
+        let part = if separator == " & " && part.contains(" | ") { format!("({})", part) } else { part };
+        if !unique.contains(&part) {
+            unique.push(part);
+        }
+    }
+    unique.join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utoipa::OpenApi;
+
+    use crate::openapi::ApiDoc;
+
+    const SNAPSHOT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/types/api.ts");
+
+    fn spec() -> Value {
+        serde_json::to_value(ApiDoc::openapi()).expect("spec serializes")
+    }
+
+    /// Fails when the models change without the frontend types being
+    /// regenerated. Run with `UPDATE_SNAPSHOTS=1` to rewrite them.
+    #[test]
+    fn types_match_snapshot() {
+        let generated = render(&spec());
+        
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(SNAPSHOT_PATH, &generated).expect("snapshot is writable");
+            return;
+        }
+        
+        let snapshot = std::fs::read_to_string(SNAPSHOT_PATH).unwrap_or_default();
+        assert!(
+            snapshot == generated,
+            "src/types/api.ts is out of date, regenerate it with `UPDATE_SNAPSHOTS=1 cargo test`"
+        );
+    }
+
+    /// The wire format converts keys and timestamps by name; every schema
+    /// property has to survive the round trip for that to be lossless.
+    #[test]
+    fn wire_format_covers_every_property() {
+        fn visit(schema: &Value) {
+            match schema {
+                Value::Object(map) => {
+                    for (name, property) in map.get("properties").and_then(Value::as_object).into_iter().flatten() {
+                        assert_eq!(wire::snake_case(&wire::camel_case(name)), *name, "{} doesn't round-trip", name);
+                        let is_date_time = property["format"] == "date-time";
+                        assert_eq!(wire::is_timestamp_key(name), is_date_time, "{} is misclassified as a timestamp", name);
+                    }
+                    map.values().for_each(visit);
+                }
+                Value::Array(items) => items.iter().for_each(visit),
+                _ => {}
+            }
+        }
+        visit(&spec()["components"]["schemas"]);
+    }
+}
@@ -0,0 +1,214 @@
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use validator::{Validate, ValidateArgs};
+
+use crate::models::{
+    validate_consumer, ConstraintViolation, EventType, FundEscrowRequest, LineItemRequest, Money, Product, Shipment,
+    ShipmentChange, ShipmentEvent, ShipmentStatus, ShipmentUpdate, UnitOfMeasure,
+};
+use crate::storage::Storage;
+
+/// The wire shapes that differ between API versions. Shipment handlers are
+/// generic over this; every other route serves the same JSON in all versions.
+pub trait ApiVersion: 'static {
+    type Shipment: Serialize + From<Shipment>;
+    type ShipmentUpdate: Serialize + From<ShipmentUpdate>;
+    type FundEscrowRequest: DeserializeOwned
+        + for<'v_a> ValidateArgs<'v_a, Args = &'v_a Storage>
+        + Into<FundEscrowRequest>;
+}
+
+/// The original payloads: escrow as a decimal amount in rupees and events
+/// without coordinates. Kept for existing clients, see `middleware::deprecated`.
+pub struct V1;
+
+/// Serves the models in `models.rs` as they are.
+pub struct V2;
+
+impl ApiVersion for V1 {
+    type Shipment = ShipmentV1;
+    type ShipmentUpdate = ShipmentUpdateV1;
+    type FundEscrowRequest = FundEscrowRequestV1;
+}
+
+impl ApiVersion for V2 {
+    type Shipment = Shipment;
+    type ShipmentUpdate = ShipmentUpdate;
+    type FundEscrowRequest = FundEscrowRequest;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShipmentV1 {
+    pub id: String,
+    pub product_id: String,
+    pub product: ProductV1,
+    pub manufacturer: String,
+    pub logistics_partner: String,
+    pub consumer: String,
+    pub status: ShipmentStatus,
+    pub escrow_amount: f64,
+    pub escrow_released: bool,
+    pub events: Vec<ShipmentEventV1>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub version: u64,
+}
+
+impl From<Shipment> for ShipmentV1 {
+    fn from(shipment: Shipment) -> Self {
+        Self {
+            id: shipment.id,
+            product_id: shipment.product_id,
+            product: shipment.product.into(),
+            manufacturer: shipment.manufacturer,
+            logistics_partner: shipment.logistics_partner,
+            consumer: shipment.consumer,
+            status: shipment.status,
+            escrow_amount: shipment.escrow.to_major(),
+            escrow_released: shipment.escrow_released,
+            events: shipment.events.into_iter().map(ShipmentEventV1::from).collect(),
+            created_at: shipment.created_at,
+            delivered_at: shipment.delivered_at,
+            confirmed_at: shipment.confirmed_at,
+            version: shipment.version,
+        }
+    }
+}
+
+/// A product as v1 clients know it: temperature limits only, and the list
+/// price in rupees.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProductV1 {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub manufacturer: String,
+    pub min_temperature: f64,
+    pub max_temperature: f64,
+    pub logistics_partner: String,
+    pub unit_price: Option<f64>,
+    pub registered_at: DateTime<Utc>,
+}
+
+impl From<Product> for ProductV1 {
+    fn from(product: Product) -> Self {
+        Self {
+            id: product.id,
+            name: product.name,
+            description: product.description,
+            manufacturer: product.manufacturer,
+            min_temperature: product.min_temperature,
+            max_temperature: product.max_temperature,
+            logistics_partner: product.logistics_partner,
+            unit_price: product.unit_price.map(|price| price.to_major()),
+            registered_at: product.registered_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShipmentEventV1 {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub location: String,
+    pub temperature: f64,
+    pub verified_temperature: f64,
+    pub reporter: String,
+    pub event_type: EventType,
+    pub is_temperature_valid: bool,
+    pub humidity: Option<f64>,
+    pub shock: Option<f64>,
+    pub light: Option<f64>,
+    pub violations: Vec<ConstraintViolation>,
+}
+
+impl From<ShipmentEvent> for ShipmentEventV1 {
+    fn from(event: ShipmentEvent) -> Self {
+        Self {
+            id: event.id,
+            timestamp: event.timestamp,
+            location: event.location,
+            temperature: event.temperature,
+            verified_temperature: event.verified_temperature,
+            reporter: event.reporter,
+            event_type: event.event_type,
+            is_temperature_valid: event.is_temperature_valid,
+            humidity: event.humidity,
+            shock: event.shock,
+            light: event.light,
+            violations: event.violations,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShipmentUpdateV1 {
+    pub shipment_id: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub change: ShipmentChangeV1,
+    pub shipment: ShipmentV1,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShipmentChangeV1 {
+    Created,
+    StatusChanged { from: ShipmentStatus, to: ShipmentStatus },
+    EventAdded { event: ShipmentEventV1 },
+    EscrowReleased { amount: f64 },
+    EscrowRefunded { amount: f64 },
+    Recalled { recall_id: String },
+}
+
+impl From<ShipmentUpdate> for ShipmentUpdateV1 {
+    fn from(update: ShipmentUpdate) -> Self {
+        let change = match update.change {
+            ShipmentChange::Created => ShipmentChangeV1::Created,
+            ShipmentChange::StatusChanged { from, to } => ShipmentChangeV1::StatusChanged { from, to },
+            ShipmentChange::EventAdded { event } => ShipmentChangeV1::EventAdded { event: event.into() },
+            ShipmentChange::EscrowReleased { amount } => ShipmentChangeV1::EscrowReleased { amount: amount.to_major() },
+            ShipmentChange::EscrowRefunded { amount } => ShipmentChangeV1::EscrowRefunded { amount: amount.to_major() },
+            ShipmentChange::Recalled { recall_id } => ShipmentChangeV1::Recalled { recall_id },
+        };
+        Self {
+            shipment_id: update.shipment_id,
+            timestamp: update.timestamp,
+            change,
+            shipment: update.shipment.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(context = "Storage")]
+pub struct FundEscrowRequestV1 {
+    #[validate(length(min = 1, message = "Product id must not be empty"))]
+    pub product_id: String,
+    #[validate(custom(function = "validate_consumer", use_context))]
+    pub consumer: String,
+    #[validate(range(exclusive_min = 0.0, message = "Escrow amount must be positive"))]
+    pub escrow_amount: f64,
+}
+
+impl From<FundEscrowRequestV1> for FundEscrowRequest {
+    fn from(req: FundEscrowRequestV1) -> Self {
+        Self {
+            consumer: req.consumer,
+            escrow: Some(Money::from_major(req.escrow_amount, Money::DEFAULT_CURRENCY)),
+            line_items: vec![LineItemRequest {
+                product_id: req.product_id,
+                lot: None,
+                expiry: None,
+                serials: vec![],
+                quantity: 1.0,
+                unit: UnitOfMeasure::Each,
+                // v1 clients agree the escrow amount themselves
+                unit_price: Some(Money::from_major(req.escrow_amount, Money::DEFAULT_CURRENCY)),
+            }],
+            logistics_partner: None,
+            legs: vec![],
+        }
+    }
+}
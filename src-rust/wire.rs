@@ -0,0 +1,89 @@
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use serde_json::{Map, Value};
+
+/// Media type for the frontend-friendly wire format: camelCase keys and
+/// timestamps as epoch milliseconds. Request it with `Accept`, send bodies in
+/// it with `Content-Type`. Plain `application/json` keeps the snake_case,
+/// RFC 3339 shape the models serialize to.
+pub const CAMEL_JSON: &str = "application/vnd.verichain.camel+json";
+
+/// Timestamp fields are recognised by name, which `typescript::tests` checks
+/// against every `date-time` property in the OpenAPI document.
+pub fn is_timestamp_key(key: &str) -> bool {
+    key == "timestamp" || key.ends_with("_at")
+}
+
+pub fn camel_case(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+pub fn snake_case(key: &str) -> String {
+    let mut out = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            out.push('_');
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Rewrites a serialized model into the camelCase/epoch-millis shape.
+pub fn to_camel(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(s) if is_timestamp_key(&key) => match DateTime::parse_from_rfc3339(&s) {
+                            Ok(timestamp) => Value::from(timestamp.timestamp_millis()),
+                            Err(_) => Value::String(s),
+                        },
+                        // Validation errors name the offending field, which the client sent in camelCase
+                        Value::String(s) if key == "field" => Value::String(s.split('.').map(camel_case).collect::<Vec<_>>().join(".")),
+                        value => to_camel(value),
+                    };
+                    (camel_case(&key), value)
+                })
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(to_camel).collect()),
+        value => value,
+    }
+}
+
+/// Rewrites a camelCase/epoch-millis request body into the shape the models deserialize.
+pub fn from_camel(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let key = snake_case(&key);
+                    let value = match value.as_i64().and_then(|millis| Utc.timestamp_millis_opt(millis).single()) {
+                        Some(timestamp) if is_timestamp_key(&key) => {
+                            Value::String(timestamp.to_rfc3339_opts(SecondsFormat::Millis, true))
+                        }
+                        _ => from_camel(value),
+                    };
+                    (key, value)
+                })
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(from_camel).collect()),
+        value => value,
+    }
+}
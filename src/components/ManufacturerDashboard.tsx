@@ -137,6 +137,7 @@ const ManufacturerDashboard: React.FC = () => {
       case 'compromised': return 'text-red-600 bg-red-100';
       case 'delivered': return 'text-green-600 bg-green-100';
       case 'confirmed': return 'text-emerald-600 bg-emerald-100';
+      case 'returning': return 'text-orange-600 bg-orange-100';
       default: return 'text-gray-600 bg-gray-100';
     }
   };
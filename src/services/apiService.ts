@@ -1,10 +1,35 @@
 // Frontend API service to communicate with Rust backend
-const API_BASE_URL = 'http://localhost:8080/api';
+import type {
+  AddEventRequest,
+  FieldError,
+  FundEscrowRequest,
+  Product,
+  QrFormat,
+  RegisterProductRequest,
+  Shipment,
+  ShipmentCertificate,
+  User,
+  VerificationSummary,
+  VerifyPasswordResponse,
+  WeatherResponse,
+} from '../types/api';
+
+const API_BASE_URL = 'http://localhost:8080/api/v2';
+
+// Asks the backend for camelCase keys and epoch-millis timestamps, matching src/types/api.ts
+const WIRE_FORMAT = 'application/vnd.verichain.camel+json';
 
 export interface ApiResponse<T> {
   success: boolean;
   data?: T;
   message: string;
+  code?: string;
+  errors?: FieldError[];
+}
+
+export interface Page<T> {
+  items: T[];
+  nextCursor: string | null;
 }
 
 class ApiService {
@@ -13,7 +38,8 @@ class ApiService {
     
     const config: RequestInit = {
       headers: {
-        'Content-Type': 'application/json',
+        'Accept': WIRE_FORMAT,
+        'Content-Type': WIRE_FORMAT,
         ...options.headers,
       },
       ...options,
@@ -34,13 +60,29 @@ class ApiService {
     }
   }
 
+  // Follows next_cursor until every page of a list endpoint has been fetched
+  private async requestAll<T>(endpoint: string): Promise<T[]> {
+    const items: T[] = [];
+    const separator = endpoint.includes('?') ? '&' : '?';
+    let cursor: string | null = null;
+
+    do {
+      const query: string = cursor ? `${separator}cursor=${encodeURIComponent(cursor)}` : '';
+      const page: Page<T> = await this.request<Page<T>>(`${endpoint}${query}`);
+      items.push(...page.items);
+      cursor = page.nextCursor;
+    } while (cursor);
+
+    return items;
+  }
+
   // Product endpoints
   async getProducts() {
-    return this.request('/products');
+    return this.requestAll<Product>('/products');
   }
 
-  async registerProduct(productData: any) {
-    return this.request('/products', {
+  async registerProduct(productData: RegisterProductRequest) {
+    return this.request<Product>('/products', {
       method: 'POST',
       body: JSON.stringify(productData),
     });
@@ -48,46 +90,79 @@ class ApiService {
 
   // Shipment endpoints
   async getShipments() {
-    return this.request('/shipments');
+    return this.requestAll<Shipment>('/shipments');
   }
 
-  async fundEscrow(escrowData: any) {
-    return this.request('/shipments', {
+  async fundEscrow(escrowData: FundEscrowRequest) {
+    return this.request<Shipment>('/shipments', {
       method: 'POST',
       body: JSON.stringify(escrowData),
     });
   }
 
-  async addEvent(shipmentId: string, eventData: any) {
-    return this.request(`/shipments/${shipmentId}/events`, {
+  async addEvent(shipmentId: string, eventData: AddEventRequest) {
+    return this.request<Shipment>(`/shipments/${shipmentId}/events`, {
       method: 'POST',
       body: JSON.stringify(eventData),
     });
   }
 
   async confirmDelivery(shipmentId: string) {
-    return this.request(`/shipments/${shipmentId}/confirm`, {
+    return this.request<Shipment>(`/shipments/${shipmentId}/confirm`, {
       method: 'POST',
     });
   }
 
   // User endpoints
   async getUsers() {
-    return this.request('/users');
+    return this.requestAll<User>('/users');
   }
 
   async getUserShipments(userId: string, role: string) {
-    return this.request(`/users/${userId}/shipments?role=${role}`);
+    return this.requestAll<Shipment>(`/users/${userId}/shipments?role=${role}`);
+  }
+
+  // Live shipment updates (server-sent events, always snake_case); close the returned source to stop
+  streamShipments(
+    filter: { shipmentId?: string; userId?: string; role?: string },
+    onUpdate: (type: string, update: any) => void,
+  ): EventSource {
+    const params = new URLSearchParams();
+    if (filter.shipmentId) params.set('shipment_id', filter.shipmentId);
+    if (filter.userId) params.set('user_id', filter.userId);
+    if (filter.role) params.set('role', filter.role);
+
+    const source = new EventSource(`${API_BASE_URL}/shipments/stream?${params}`);
+    for (const type of ['created', 'status_changed', 'event_added', 'escrow_released', 'escrow_refunded', 'recalled', 'lagged']) {
+      source.addEventListener(type, (event) => onUpdate(type, JSON.parse((event as MessageEvent).data)));
+    }
+    return source;
+  }
+
+  // Image URL of the shipment's verification QR code, rendered by the backend
+  shipmentQrUrl(shipmentId: string, size: number, format: QrFormat = 'svg'): string {
+    const params = new URLSearchParams({ format, size: String(size) });
+    return `${API_BASE_URL}/shipments/${encodeURIComponent(shipmentId)}/qr?${params}`;
+  }
+
+  // Public summary for a scanned verification token
+  async verifyShipment(token: string) {
+    return this.request<VerificationSummary>(`/verify/${encodeURIComponent(token)}`);
+  }
+
+  // Offline-verifiable certificate for a shipment (compact JWS plus its decoded claims)
+  async getShipmentCertificate(shipmentId: string) {
+    return this.request<ShipmentCertificate>(`/shipments/${encodeURIComponent(shipmentId)}/certificate`);
   }
 
   // Weather endpoint
   async getWeather(location: string) {
-    return this.request(`/weather/${location}`);
+    return this.request<WeatherResponse>(`/weather/${location}`);
   }
 
   // Authentication endpoint
   async verifyPassword(password: string, action: string) {
-    return this.request('/auth/verify', {
+    return this.request<VerifyPasswordResponse>('/auth/verify', {
       method: 'POST',
       body: JSON.stringify({ password, action }),
     });
@@ -1,4 +1,5 @@
 import { Product, Shipment, ShipmentEvent, User } from '../types/blockchain';
+import type { Product as ApiProduct, Shipment as ApiShipment } from '../types/api';
 import { apiService } from './apiService';
 
 // Updated blockchain service to use Rust backend API
@@ -91,43 +92,43 @@ class BlockchainService {
   }
 
   // Convert backend data format to frontend format
-  private convertBackendProduct(backendProduct: any): Product {
+  private convertBackendProduct(backendProduct: ApiProduct): Product {
     return {
       id: backendProduct.id,
       name: backendProduct.name,
       description: backendProduct.description,
       manufacturer: backendProduct.manufacturer,
-      minTemperature: backendProduct.min_temperature,
-      maxTemperature: backendProduct.max_temperature,
-      logisticsPartner: backendProduct.logistics_partner,
-      registeredAt: new Date(backendProduct.registered_at).getTime()
+      minTemperature: backendProduct.minTemperature,
+      maxTemperature: backendProduct.maxTemperature,
+      logisticsPartner: backendProduct.logisticsPartner,
+      registeredAt: backendProduct.registeredAt
     };
   }
 
-  private convertBackendShipment(backendShipment: any): Shipment {
+  private convertBackendShipment(backendShipment: ApiShipment): Shipment {
     return {
       id: backendShipment.id,
-      productId: backendShipment.product_id,
+      productId: backendShipment.productId,
       product: this.convertBackendProduct(backendShipment.product),
       manufacturer: backendShipment.manufacturer,
-      logisticsPartner: backendShipment.logistics_partner,
+      logisticsPartner: backendShipment.logisticsPartner,
       consumer: backendShipment.consumer,
-      status: backendShipment.status.toLowerCase().replace('_', '_') as any,
-      escrowAmount: backendShipment.escrow_amount,
-      escrowReleased: backendShipment.escrow_released,
-      events: backendShipment.events.map((event: any) => ({
+      status: backendShipment.status,
+      escrowAmount: backendShipment.escrow.amountMinor / 100,
+      escrowReleased: backendShipment.escrowReleased,
+      events: backendShipment.events.map((event) => ({
         id: event.id,
-        timestamp: new Date(event.timestamp).getTime(),
+        timestamp: event.timestamp,
         location: event.location,
         temperature: event.temperature,
-        verifiedTemperature: event.verified_temperature,
+        verifiedTemperature: event.verifiedTemperature,
         reporter: event.reporter,
-        eventType: event.event_type.toLowerCase() as any,
-        isTemperatureValid: event.is_temperature_valid
+        eventType: event.eventType,
+        isTemperatureValid: event.isTemperatureValid
       })),
-      createdAt: new Date(backendShipment.created_at).getTime(),
-      deliveredAt: backendShipment.delivered_at ? new Date(backendShipment.delivered_at).getTime() : undefined,
-      confirmedAt: backendShipment.confirmed_at ? new Date(backendShipment.confirmed_at).getTime() : undefined
+      createdAt: backendShipment.createdAt,
+      deliveredAt: backendShipment.deliveredAt ?? undefined,
+      confirmedAt: backendShipment.confirmedAt ?? undefined
     };
   }
 
@@ -139,9 +140,9 @@ class BlockchainService {
           name: productData.name,
           description: productData.description,
           manufacturer: productData.manufacturer,
-          min_temperature: productData.minTemperature,
-          max_temperature: productData.maxTemperature,
-          logistics_partner: productData.logisticsPartner
+          minTemperature: productData.minTemperature,
+          maxTemperature: productData.maxTemperature,
+          logisticsPartner: productData.logisticsPartner
         };
         
         const result = await apiService.registerProduct(backendData);
@@ -166,10 +167,11 @@ class BlockchainService {
   async fundEscrow(productId: string, consumer: string, escrowAmount: number): Promise<Shipment> {
     if (this.useBackend) {
       try {
+        const escrow = { amountMinor: Math.round(escrowAmount * 100), currency: 'INR' };
         const escrowData = {
-          product_id: productId,
           consumer,
-          escrow_amount: escrowAmount
+          escrow,
+          lineItems: [{ productId, quantity: 1, unit: 'each' as const, unitPrice: escrow }]
         };
         
         const result = await apiService.fundEscrow(escrowData);
@@ -217,7 +219,7 @@ class BlockchainService {
         const eventData = {
           location,
           temperature: reportedTemperature,
-          event_type: eventType,
+          eventType,
           reporter
         };
         
@@ -305,7 +307,7 @@ class BlockchainService {
     if (this.useBackend) {
       try {
         const result = await apiService.getProducts();
-        return result.map((p: any) => this.convertBackendProduct(p));
+        return result.map((p) => this.convertBackendProduct(p));
       } catch (error) {
         console.error('Backend call failed, using mock data:', error);
         this.useBackend = false;
@@ -319,7 +321,7 @@ class BlockchainService {
     if (this.useBackend) {
       try {
         const result = await apiService.getShipments();
-        return result.map((s: any) => this.convertBackendShipment(s));
+        return result.map((s) => this.convertBackendShipment(s));
       } catch (error) {
         console.error('Backend call failed, using mock data:', error);
         this.useBackend = false;
@@ -333,7 +335,7 @@ class BlockchainService {
     if (this.useBackend) {
       try {
         const result = await apiService.getUserShipments(userId, role);
-        return result.map((s: any) => this.convertBackendShipment(s));
+        return result.map((s) => this.convertBackendShipment(s));
       } catch (error) {
         console.error('Backend call failed, using mock data:', error);
         this.useBackend = false;
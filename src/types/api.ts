@@ -0,0 +1,596 @@
+// Generated from the Rust models by `cargo test`, do not edit.
+// Shapes match responses requested with `Accept: application/vnd.verichain.camel+json`.
+
+export interface AddEventRequest {
+  coordinates?: null | Coordinates;
+  eventType: EventType;
+  humidity?: number | null;
+  light?: number | null;
+  location: string;
+  reporter?: string;
+  shock?: number | null;
+  temperature: number;
+}
+
+export interface AssignPartnerRequest {
+  /** Index of the leg to reassign; the first by default. */
+  leg?: number;
+  logisticsPartner: string;
+}
+
+export interface CancelShipmentRequest {
+  reason: string;
+  /** The shipment's manufacturer or consumer. */
+  requestedBy: string;
+}
+
+export interface Cancellation {
+  cancelledAt: number;
+  reason: string;
+  requestedBy: string;
+}
+
+/** Signed contents of a shipment certificate. An inspector who trusts the signing key learns the public summary and the head of the event hash chain, which later copies of the custody record can be checked against. */
+export interface CertificateClaims {
+  /** Hash of the latest custody event when the certificate was issued. */
+  chainHead: string;
+  eventCount: number;
+  issuedAt: number;
+  issuer: string;
+  summary: VerificationSummary;
+}
+
+/** The published half of the certificate signing key. */
+export interface CertificateKey {
+  algorithm: string;
+  /** Key id carried in the `kid` header of every certificate. */
+  kid: string;
+  /** Raw 32-byte Ed25519 public key, base64url without padding. */
+  publicKey: string;
+}
+
+export interface ConstraintViolation {
+  kind: ViolationKind;
+  limit: number;
+  metric: EnvironmentalMetric;
+  value: number;
+}
+
+/** A WGS 84 position in decimal degrees. */
+export interface Coordinates {
+  latitude: number;
+  longitude: number;
+}
+
+export interface CreateRecallRequest {
+  /** Must be the product's manufacturer. */
+  issuedBy: string;
+  /** Limits the recall to one lot; omit to recall every lot. */
+  lot?: string | null;
+  productId: string;
+  reason: string;
+  shippedFrom?: string | null;
+  shippedUntil?: string | null;
+}
+
+export interface CreateWebhookRequest {
+  events: WebhookEventType[];
+  url: string;
+}
+
+export interface DeliveryAttempt {
+  attemptedAt: number;
+  durationMs: number;
+  error?: string | null;
+  statusCode?: number | null;
+}
+
+export type DeliveryStatus = "pending" | "delivered" | "failed";
+
+export interface Device {
+  createdAt: number;
+  id: string;
+  keyPrefix: string;
+  lastSeenAt?: number | null;
+  name: string;
+  owner: string;
+  revokedAt?: number | null;
+  rotatedAt?: number | null;
+  status: DeviceStatus;
+}
+
+export interface DeviceCredentials {
+  apiKey: string;
+  device: Device;
+}
+
+export type DeviceStatus = "active" | "revoked";
+
+export interface EnvironmentalConstraint {
+  max?: number | null;
+  metric: EnvironmentalMetric;
+  min?: number | null;
+}
+
+/** Measured quantities a product can be constrained on. Units are °C for temperature, % relative humidity, lux for light and g for shock. */
+export type EnvironmentalMetric = "temperature" | "humidity" | "light" | "shock";
+
+/** Documents the body `ApiError` renders, i.e. an `ApiResponse<()>` with a `code` and, for `validation_failed`, the individual field `errors`. */
+export interface ErrorResponse {
+  code: string;
+  data?: unknown;
+  errors?: FieldError[] | null;
+  message: string;
+  success: boolean;
+}
+
+export type EventType = "pickup" | "transit" | "delivery";
+
+/** A single failed rule, reported in `ApiResponse::errors`. */
+export interface FieldError {
+  code: string;
+  /** The offending request field, or null for rules spanning several fields. */
+  field?: string | null;
+  message: string;
+}
+
+export interface FundEscrowRequest {
+  consumer: string;
+  escrow?: null | Money;
+  /** Logistics partner of each leg of the route, in order. Without these or `logistics_partner`, the first product's partner carries it all the way. */
+  legs?: string[];
+  lineItems: LineItemRequest[];
+  /** Carrier for the whole route, instead of `legs`. */
+  logisticsPartner?: string | null;
+}
+
+/** GS1 identification of a trade item, a lot of it or a single serialized unit (SGTIN). */
+export interface Gs1Identifiers {
+  /** Expiry date (AI 17). */
+  expiry?: string | null;
+  /** GTIN-14 (AI 01). */
+  gtin: string;
+  /** Batch/lot (AI 10). */
+  lot?: string | null;
+  /** Serial number (AI 21). */
+  serial?: string | null;
+}
+
+/** One label's worth of GS1 data: per serialized unit, or per lot for unserialized shipments. */
+export interface Gs1Label {
+  /** GS1-128 barcode data with group separators (U+001D) after variable-length elements. */
+  barcodeData: string;
+  digitalLink: string;
+  /** Human-readable GS1-128 text, e.g. `(01)09506000134352(10)LOT1`. */
+  elementString: string;
+  identifiers: Gs1Identifiers;
+}
+
+export interface Gs1ParseRequest {
+  /** A Digital Link URI, bracketed element string or raw scanned barcode data. */
+  data: string;
+}
+
+/** Custody passes between legs only once both partners have acknowledged it. */
+export interface Handoff {
+  location: string;
+  /** When the incoming partner acknowledged receiving them. */
+  receivedAt?: number | null;
+  /** When the outgoing partner acknowledged releasing the goods. */
+  releasedAt?: number | null;
+}
+
+export interface HandoffRequest {
+  /** Index of the leg ending at the handoff. */
+  leg: number;
+  location: string;
+  /** The leg's partner or the next leg's; each must acknowledge. */
+  userId: string;
+}
+
+/** A stretch of the route carried by one logistics partner. */
+export interface Leg {
+  /** Handoff to the next leg, or delivery for the last. */
+  endedAt?: number | null;
+  handoff?: null | Handoff;
+  partner: string;
+  /** Pickup for the first leg, otherwise the handoff from the previous leg. */
+  startedAt?: number | null;
+}
+
+/** One product, and optionally one lot of it, in a shipment. */
+export interface LineItem {
+  /** Expiry date of the lot (AI 17). */
+  expiry?: string | null;
+  /** GS1 batch/lot number (AI 10). */
+  lot?: string | null;
+  /** The product as it was when the shipment was funded. */
+  product: Product;
+  productId: string;
+  quantity: number;
+  /** Serial numbers (AI 21) of the individual units, which together with the product's GTIN form their SGTINs. */
+  serials?: string[];
+  unit: UnitOfMeasure;
+  unitPrice?: null | Money;
+}
+
+export interface LineItemRequest {
+  expiry?: string | null;
+  /** Requires a product with a GTIN, as do `expiry` and `serials`. */
+  lot?: string | null;
+  productId: string;
+  quantity: number;
+  /** One per unit; requires `unit` to be `each`. */
+  serials?: string[];
+  unit?: UnitOfMeasure;
+  unitPrice?: null | Money;
+}
+
+/** How much of a lot one shipment carries and where that shipment is. */
+export interface LotShipment {
+  consumer: string;
+  createdAt: number;
+  deliveredAt?: number | null;
+  logisticsPartner: string;
+  manufacturer: string;
+  quantity: number;
+  serials: string[];
+  shipmentId: string;
+  status: ShipmentStatus;
+}
+
+/** A lot of a product and how much of it has been shipped. */
+export interface LotSummary {
+  expiry?: string | null;
+  lot: string;
+  quantity: number;
+  shipmentCount: number;
+  unit: UnitOfMeasure;
+}
+
+/** Every shipment carrying a lot of a product, oldest first. */
+export interface LotTrace {
+  expiry?: string | null;
+  lot: string;
+  productId: string;
+  quantity: number;
+  shipments: LotShipment[];
+  unit: UnitOfMeasure;
+}
+
+/** An amount of money in the minor unit of its currency (paise for INR), so escrow arithmetic is exact. */
+export interface Money {
+  amountMinor: number;
+  /** ISO 4217 currency code. */
+  currency: string;
+}
+
+export interface Product {
+  /** Limits beyond temperature, e.g. humidity, light or shock. */
+  constraints?: EnvironmentalConstraint[];
+  /** Set when the product is deregistered. Deleted products stay readable so existing shipments can still resolve them, but can't be shipped again. */
+  deletedAt?: number | null;
+  description: string;
+  /** Cumulative minutes the product may spend outside its temperature range before a shipment is marked compromised. Zero means no tolerance. */
+  excursionBudgetMinutes?: number;
+  /** GS1 trade item number, zero-padded to 14 digits. */
+  gtin?: string | null;
+  id: string;
+  /** Default carrier for shipments that don't choose their own. */
+  logisticsPartner: string;
+  manufacturer: string;
+  maxTemperature: number;
+  minTemperature: number;
+  name: string;
+  registeredAt: number;
+  unitPrice?: null | Money;
+  updatedAt?: number | null;
+}
+
+export type QrFormat = "png" | "svg";
+
+/** A manufacturer's recall of a product, optionally narrowed to one lot and to shipments created within a date range. */
+export interface Recall {
+  closedAt?: number | null;
+  createdAt: number;
+  id: string;
+  issuedBy: string;
+  lot?: string | null;
+  productId: string;
+  reason: string;
+  /** Every shipment the recall flagged when it was issued. */
+  shipmentIds: string[];
+  /** First day of shipments covered, inclusive (UTC). */
+  shippedFrom?: string | null;
+  /** Last day of shipments covered, inclusive (UTC). */
+  shippedUntil?: string | null;
+  status: RecallStatus;
+}
+
+/** A recall and where each affected shipment is now. */
+export interface RecallReport {
+  recall: Recall;
+  shipments: RecalledShipment[];
+}
+
+export type RecallStatus = "open" | "closed";
+
+export interface RecalledShipment {
+  consumer: string;
+  /** Whoever holds the goods now, see `Shipment::custodian`. */
+  custodian: string;
+  escrowFrozen: boolean;
+  /** Location of the last custody event, if there has been one. */
+  lastLocation?: string | null;
+  lots: string[];
+  quantity: number;
+  serials: string[];
+  shipmentId: string;
+  status: ShipmentStatus;
+  unit: UnitOfMeasure;
+}
+
+export interface RegisterDeviceRequest {
+  name: string;
+  owner: string;
+}
+
+export interface RegisterProductRequest {
+  constraints?: EnvironmentalConstraint[];
+  description: string;
+  excursionBudgetMinutes?: number;
+  /** GTIN-8, -12, -13 or -14; stored as GTIN-14. */
+  gtin?: string | null;
+  /** Default carrier; each shipment may choose another. */
+  logisticsPartner: string;
+  manufacturer: string;
+  maxTemperature: number;
+  minTemperature: number;
+  name: string;
+  unitPrice?: null | Money;
+}
+
+export interface ReturnDetails {
+  /** Whether the goods stayed within the product's limits on the way back. */
+  coldChainIntact: boolean;
+  details?: string | null;
+  reason: ReturnReason;
+  requestedAt: number;
+  requestedBy: string;
+  /** When the manufacturer took the goods back. */
+  returnedAt?: number | null;
+  /** The shipment's status when the return was requested. */
+  returnedFrom: ShipmentStatus;
+}
+
+/** Why goods are sent back, which decides how much escrow the consumer gets back. */
+export type ReturnReason = "refused" | "damaged" | "wrong_item" | "recalled";
+
+export interface ReturnShipmentRequest {
+  details?: string | null;
+  reason: ReturnReason;
+  /** The shipment's manufacturer or consumer. */
+  requestedBy: string;
+}
+
+export interface Shipment {
+  cancellation?: null | Cancellation;
+  /** When the cold chain was first broken, outbound or on a return. Unlike the status, it survives the shipment being returned. */
+  compromisedAt?: number | null;
+  /** Index into `legs` of the leg in progress when the shipment was compromised, or null if it happened before pickup or hasn't happened. */
+  compromisedLeg?: number | null;
+  confirmedAt?: number | null;
+  consumer: string;
+  createdAt: number;
+  deliveredAt?: number | null;
+  escrow: Money;
+  escrowRefund?: null | Money;
+  escrowReleased: boolean;
+  events: ShipmentEvent[];
+  id: string;
+  /** The carriers' legs of the route, in order. `logistics_partner` is the partner of the leg in progress. */
+  legs?: Leg[];
+  lineItems: LineItem[];
+  logisticsPartner: string;
+  manufacturer: string;
+  product: Product;
+  /** The first line item's product, for clients that expect one product per shipment. */
+  productId: string;
+  /** Open recalls covering the shipment. Escrow is frozen while any remain. */
+  recalls?: string[];
+  returnDetails?: null | ReturnDetails;
+  status: ShipmentStatus;
+  /** Incremented on every stored update; used for optimistic concurrency. */
+  version?: number;
+}
+
+export interface ShipmentCertificate {
+  /** Compact JWS (`header.payload.signature`) over the claims, signed with EdDSA/Ed25519. */
+  certificate: string;
+  claims: CertificateClaims;
+}
+
+export type ShipmentChange = {
+  type: "created";
+} | {
+  from: ShipmentStatus;
+  to: ShipmentStatus;
+  type: "status_changed";
+} | {
+  event: ShipmentEvent;
+  type: "event_added";
+} | {
+  amount: Money;
+  type: "escrow_released";
+} | {
+  amount: Money;
+  type: "escrow_refunded";
+} | {
+  recallId: string;
+  type: "recalled";
+};
+
+export interface ShipmentEvent {
+  coordinates?: null | Coordinates;
+  eventType: EventType;
+  /** Links the event to the one before it, see `EventChainService`. */
+  hash?: string;
+  humidity?: number | null;
+  id: string;
+  isTemperatureValid: boolean;
+  light?: number | null;
+  location: string;
+  reporter: string;
+  shock?: number | null;
+  temperature: number;
+  timestamp: number;
+  verifiedTemperature: number;
+  violations?: ConstraintViolation[];
+}
+
+export type ShipmentStatus = "pending" | "in_transit" | "compromised" | "delivered" | "confirmed" | "cancelled" | "returning" | "returned";
+
+/** A change to a shipment, broadcast to live subscribers as it is stored. */
+export type ShipmentUpdate = ShipmentChange & {
+  shipment: Shipment;
+  shipmentId: string;
+  timestamp: number;
+};
+
+export interface TelemetryBatchRequest {
+  readings: TelemetryReading[];
+}
+
+export interface TelemetryBatchResponse {
+  accepted: number;
+  excursions: TelemetryExcursion[];
+  status: ShipmentStatus;
+}
+
+export interface TelemetryExcursion {
+  timestamp: number;
+  violations: ConstraintViolation[];
+}
+
+export interface TelemetryReading {
+  humidity?: number | null;
+  light?: number | null;
+  shock?: number | null;
+  temperature?: number | null;
+  timestamp: number;
+}
+
+export interface ThermalSummary {
+  budgetExceeded: boolean;
+  /** When the excursions ran through the budget, if they have. */
+  budgetExceededAt?: number | null;
+  excursionBudgetMinutes: number;
+  firstReadingAt?: number | null;
+  lastReadingAt?: number | null;
+  maxTemperature?: number | null;
+  meanKineticTemperature?: number | null;
+  minTemperature?: number | null;
+  minutesAboveMax: number;
+  minutesBelowMin: number;
+  readingCount: number;
+  shipmentId: string;
+}
+
+/** Units a line item's quantity can be counted or measured in. */
+export type UnitOfMeasure = "each" | "kilogram" | "litre";
+
+export interface UpdateProductRequest {
+  description?: string | null;
+  name?: string | null;
+}
+
+export interface User {
+  address: string;
+  id: string;
+  name: string;
+  role: UserRole;
+}
+
+export type UserRole = "manufacturer" | "logistics" | "consumer";
+
+/** What anyone holding a shipment's verification token may see. Escrow, internal ids and the logistics and consumer parties are left out. */
+export interface VerificationSummary {
+  /** No custody event breached the product's limits. */
+  coldChainIntact: boolean;
+  deliveredAt?: number | null;
+  items: VerifiedItem[];
+  journey: VerifiedEvent[];
+  /** Manufacturer name. */
+  manufacturer?: string | null;
+  /** The custody events still match their hash chain. */
+  recordIntact: boolean;
+  shippedAt: number;
+  status: ShipmentStatus;
+}
+
+export interface VerifiedEvent {
+  eventType: EventType;
+  location: string;
+  timestamp: number;
+  withinLimits: boolean;
+}
+
+export interface VerifiedItem {
+  description: string;
+  expiry?: string | null;
+  gtin?: string | null;
+  lot?: string | null;
+  maxTemperature: number;
+  minTemperature: number;
+  name: string;
+  quantity: number;
+  unit: UnitOfMeasure;
+}
+
+export interface VerifyPasswordRequest {
+  action: string;
+  password: string;
+}
+
+export interface VerifyPasswordResponse {
+  message: string;
+  valid: boolean;
+}
+
+export type ViolationKind = "below_min" | "above_max";
+
+export interface WeatherResponse {
+  conditions: string;
+  humidity: number;
+  location: string;
+  temperature: number;
+  timestamp: number;
+}
+
+export interface WebhookCredentials {
+  secret: string;
+  subscription: WebhookSubscription;
+}
+
+export interface WebhookDelivery {
+  attempts: DeliveryAttempt[];
+  createdAt: number;
+  eventType: WebhookEventType;
+  id: string;
+  nextAttemptAt?: number | null;
+  payload: unknown;
+  /** Attempts since the delivery was (re)queued; drives the retry backoff. */
+  retries: number;
+  status: DeliveryStatus;
+  subscriptionId: string;
+}
+
+export type WebhookEventType = "shipment.created" | "shipment.status_changed" | "shipment.compromised" | "shipment.event_added" | "escrow.released" | "shipment.recalled" | "escrow.refunded";
+
+export interface WebhookSubscription {
+  createdAt: number;
+  events: WebhookEventType[];
+  id: string;
+  url: string;
+  userId: string;
+}
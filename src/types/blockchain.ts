@@ -28,7 +28,7 @@ export interface Shipment {
   manufacturer: string;
   logisticsPartner: string;
   consumer: string;
-  status: 'pending' | 'in_transit' | 'compromised' | 'delivered' | 'confirmed';
+  status: 'pending' | 'in_transit' | 'compromised' | 'delivered' | 'confirmed' | 'cancelled' | 'returning' | 'returned';
   escrowAmount: number;
   escrowReleased: boolean;
   events: ShipmentEvent[];